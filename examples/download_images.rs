@@ -1,70 +1,59 @@
-use std::path::PathBuf;
-
-use futures::{stream, StreamExt, TryStreamExt};
+use futures::StreamExt;
 use hitomi_la::{
+    download::{Event, GalleryDownloader},
     gallery,
-    gg::GG,
-    image::{self, Image, ImageExt, ImageKind},
     nozomi::{self, Language},
 };
-use tokio::fs;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let base_dir = PathBuf::from("./galleries");
-
     let ids = nozomi::parse(Language::Korean, 1, 24).await?;
 
     println!("nozomi: {:?}", ids);
 
-    let gg = GG::from_hitomi().await?;
-
-    let galleries = stream::iter(ids)
-        .map(gallery::parse)
-        .buffered(8)
-        .try_collect::<Vec<_>>()
-        .await?;
+    let galleries = gallery::parse_many(ids, 8)
+        .filter_map(|(id, res)| async move {
+            match res {
+                Ok(gallery) => gallery,
+                Err(err) => {
+                    println!("gallery: {} failed: {}", id, err);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .await;
 
-    let gallery = galleries
-        .into_iter()
-        .flatten()
-        .min_by_key(|g| g.files.len())
-        .unwrap();
+    let gallery = galleries.into_iter().min_by_key(|g| g.files.len()).unwrap();
 
     println!("gallery: {:?}", gallery);
 
-    let gallery_dir = base_dir.join(gallery.id.to_string());
-
-    println!("create dir: {}", gallery_dir.as_os_str().to_string_lossy());
-
-    fs::create_dir_all(&gallery_dir).await?;
-
-    stream::iter(gallery.files)
-        // page: starts from 1
-        .map(|(page, file)| {
-            let gg = &gg;
-            let gallery_dir = &gallery_dir;
+    let mut events = GalleryDownloader::new(gallery, "./galleries")
+        .concurrency(4)
+        .run();
 
-            async move {
-                println!("download: start {}", page);
-
-                let image: Image =
-                    image::download(&file, ImageKind::Original, ImageExt::Avif, gg).await?;
-
-                println!("download: complete {}", page);
-
-                let image_dir = gallery_dir.join(format!("{}.{}", page, image.ext));
-
-                fs::write(&image_dir, image.buf).await?;
-
-                println!("write: {}", image_dir.as_os_str().to_string_lossy());
-
-                Ok::<(), anyhow::Error>(())
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Started { id, title, pages } => {
+                println!("start: {} {} ({} pages)", id, title, pages)
             }
-        })
-        .buffer_unordered(4)
-        .try_collect::<()>()
-        .await?;
+            Event::Progress { .. } => {}
+            Event::PageDone { page, path, .. } => {
+                println!("write: {} {}", page, path.as_os_str().to_string_lossy())
+            }
+            Event::PageFailed { page, error } => println!("download: {} failed: {}", page, error),
+            Event::PageSkipped { page, .. } => println!("skip: {}", page),
+            Event::Finished {
+                succeeded,
+                skipped,
+                failed,
+            } => println!(
+                "finish: {} succeeded, {} skipped, {} failed",
+                succeeded, skipped, failed
+            ),
+            Event::Aborted(err) => return Err(err.into()),
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,112 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+/// Parses datetime of hitomi
+///
+/// Accepts formats hitomi has used:
+///
+/// - `2024-01-02 03:04-05`
+/// - `2024-01-02 03:04:05+09`
+/// - `2024-01-02T03:04:05.678+0900`
+/// - `2024-01-02 03:04:05+09:00`
+/// - `2024-01-02 03:04:05Z`
+/// - `2024-01-02` (midnight of UTC)
+///
+/// Datetime without offset is regarded as UTC.
+pub(crate) fn parse(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+
+    let date = NaiveDate::parse_from_str(s.get(..10)?, "%Y-%m-%d").ok()?;
+
+    let rest = &s[10..];
+
+    if rest.is_empty() {
+        return Some(date.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    let time = rest.strip_prefix([' ', 'T'])?.trim_start();
+
+    let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => match time.find(['+', '-']) {
+            Some(i) => (&time[..i], parse_offset(&time[i..])?),
+            None => (time, 0),
+        },
+    };
+
+    let time = NaiveTime::parse_from_str(time.trim_end(), "%H:%M:%S%.f")
+        .or_else(|_| NaiveTime::parse_from_str(time.trim_end(), "%H:%M"))
+        .ok()?;
+
+    FixedOffset::east_opt(offset)?
+        .from_local_datetime(&NaiveDateTime::new(date, time))
+        .single()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+/// `+09`, `+0900`, `+09:00` to seconds
+fn parse_offset(s: &str) -> Option<i32> {
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+
+    let digits = s[1..].replace(':', "");
+
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i32>().ok()?,
+            digits[2..].parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_date() {
+        let expected = "2024-01-02T08:04:00+00:00";
+
+        for s in [
+            "2024-01-02 03:04-05",
+            "2024-01-02 03:04:00-05",
+            "2024-01-02T03:04:00.000-0500",
+            "2024-01-02 03:04:00-05:00",
+            "2024-01-02 17:04+09",
+            "2024-01-02 08:04:00Z",
+            "2024-01-02 08:04",
+            " 2024-01-02 08:04:00+0000 ",
+        ] {
+            assert_eq!(
+                parse(s).map(|x| x.to_rfc3339()).as_deref(),
+                Some(expected),
+                "{s}"
+            );
+        }
+
+        assert_eq!(
+            parse("2024-01-02").map(|x| x.to_rfc3339()).as_deref(),
+            Some("2024-01-02T00:00:00+00:00")
+        );
+
+        for s in [
+            "",
+            "2024-01-02T",
+            "2024-01-02 03",
+            "2024-01-02 03:04+5",
+            "hello",
+        ] {
+            assert_eq!(parse(s), None, "{s}");
+        }
+    }
+}
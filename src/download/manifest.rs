@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{image::ImageExt, model};
+
+use super::Error;
+
+/// File name of manifest in gallery directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+const VERSION: u32 = 1;
+
+/// Download state of a gallery, saved in gallery directory
+///
+/// Lets next download skip pages already done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub gallery: model::Gallery,
+    /// Keyed by page, which starts from 1
+    pub pages: BTreeMap<usize, PageEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageEntry {
+    /// [`model::File::hash`], a page is downloaded again if it's changed
+    pub hash: String,
+    pub status: PageStatus,
+    /// Ext actually saved, set when done
+    pub ext: Option<ImageExt>,
+    /// Relative to gallery directory, set when done
+    pub file_name: Option<String>,
+    /// Set when done
+    pub size: Option<u64>,
+    /// Set when failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+impl PageEntry {
+    fn pending(hash: &str) -> Self {
+        PageEntry {
+            hash: hash.to_owned(),
+            status: PageStatus::Pending,
+            ext: None,
+            file_name: None,
+            size: None,
+            error: None,
+        }
+    }
+}
+
+impl Manifest {
+    /// Every page is pending
+    pub fn new(gallery: model::Gallery) -> Self {
+        Self::resume(gallery, None)
+    }
+
+    /// Carries done pages over from `previous` if their hashes are unchanged
+    pub fn resume(gallery: model::Gallery, previous: Option<Manifest>) -> Self {
+        let mut previous = previous.map(|m| m.pages).unwrap_or_default();
+
+        let pages = gallery
+            .files
+            .iter()
+            .map(|(page, file)| {
+                let entry = previous
+                    .remove(page)
+                    .filter(|entry| entry.status == PageStatus::Done && entry.hash == file.hash)
+                    .unwrap_or_else(|| PageEntry::pending(&file.hash));
+
+                (*page, entry)
+            })
+            .collect();
+
+        Manifest {
+            version: VERSION,
+            gallery,
+            pages,
+        }
+    }
+
+    /// Reads manifest in `dir`, `None` if there's no manifest
+    ///
+    /// ## Errors
+    ///
+    /// - [`Error::UnsupportedManifestVersion`] if it's written in another version
+    pub async fn load(dir: impl AsRef<Path>) -> crate::Result<Option<Self>> {
+        let path = dir.as_ref().join(MANIFEST_FILE_NAME);
+
+        let buf = match fs::read(&path).await {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let manifest: Manifest = serde_json::from_slice(&buf)
+            .map_err(|err| Error::DeserializeManifest(path.clone(), err))?;
+
+        if manifest.version != VERSION {
+            return Err(Error::UnsupportedManifestVersion(path, manifest.version).into());
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Writes manifest into `dir`
+    ///
+    /// Written to a temporary file and renamed, so a crash never leaves a broken manifest.
+    pub async fn save(&self, dir: impl AsRef<Path>) -> crate::Result<()> {
+        let path = dir.as_ref().join(MANIFEST_FILE_NAME);
+        let tmp = dir.as_ref().join(format!("{MANIFEST_FILE_NAME}.tmp"));
+
+        let buf = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+
+        fs::write(&tmp, buf).await?;
+        fs::rename(&tmp, &path).await?;
+
+        Ok(())
+    }
+
+    pub fn page(&self, page: usize) -> Option<&PageEntry> {
+        self.pages.get(&page)
+    }
+
+    /// Pages which are not done yet
+    pub fn remaining(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pages
+            .iter()
+            .filter(|(_, entry)| entry.status != PageStatus::Done)
+            .map(|(page, _)| *page)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining().next().is_none()
+    }
+
+    pub(crate) fn set_done(&mut self, page: usize, ext: ImageExt, file_name: String, size: u64) {
+        if let Some(entry) = self.pages.get_mut(&page) {
+            entry.status = PageStatus::Done;
+            entry.ext = Some(ext);
+            entry.file_name = Some(file_name);
+            entry.size = Some(size);
+            entry.error = None;
+        }
+    }
+
+    pub(crate) fn set_failed(&mut self, page: usize, error: &crate::Error) {
+        if let Some(entry) = self.pages.get_mut(&page) {
+            entry.status = PageStatus::Failed;
+            entry.ext = None;
+            entry.file_name = None;
+            entry.size = None;
+            entry.error = Some(error.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{gallery, temp_dir};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resume_manifest() {
+        let mut manifest = Manifest::new(gallery(&["a", "b", "c"]));
+
+        manifest.set_done(1, ImageExt::Avif, "1.avif".to_owned(), 10);
+        manifest.set_done(2, ImageExt::Webp, "2.webp".to_owned(), 20);
+        manifest.set_failed(3, &Error::GalleryNotFound(1).into());
+
+        assert_eq!(manifest.remaining().collect::<Vec<_>>(), [3]);
+
+        let dir = temp_dir("resume_manifest");
+
+        manifest.save(&dir).await.unwrap();
+        let loaded = Manifest::load(&dir).await.unwrap().unwrap();
+
+        fs::remove_dir_all(&dir).await.ok();
+
+        assert_eq!(loaded.pages, manifest.pages);
+
+        // page 2 is changed, page 4 is added
+        let resumed = Manifest::resume(gallery(&["a", "x", "c", "d"]), Some(loaded));
+
+        assert_eq!(resumed.page(1), manifest.page(1));
+        assert_eq!(resumed.page(2).unwrap().status, PageStatus::Pending);
+        assert_eq!(resumed.page(3).unwrap().status, PageStatus::Pending);
+        assert_eq!(resumed.remaining().collect::<Vec<_>>(), [2, 3, 4]);
+        assert!(!resumed.is_complete());
+    }
+
+    #[tokio::test]
+    async fn reject_other_version() {
+        let manifest = Manifest {
+            version: VERSION + 1,
+            ..Manifest::new(gallery(&["a"]))
+        };
+
+        let dir = temp_dir("reject_other_version");
+
+        manifest.save(&dir).await.unwrap();
+        let res = Manifest::load(&dir).await;
+
+        fs::remove_dir_all(&dir).await.ok();
+
+        assert!(matches!(
+            res,
+            Err(crate::Error::Download(Error::UnsupportedManifestVersion(_, version))) if version == VERSION + 1
+        ));
+    }
+}
@@ -0,0 +1,792 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{channel::mpsc, future, stream, stream::BoxStream, StreamExt};
+use reqwest::StatusCode;
+use tokio::{fs, io::AsyncReadExt, sync::Mutex};
+
+mod manifest;
+mod template;
+
+pub use manifest::*;
+pub use template::*;
+
+use crate::{
+    export::Sidecar,
+    gallery,
+    gg::GgCache,
+    image::{self, ImageExt, ImageKind, Verifier, Verify},
+    model::{self, File},
+    network,
+};
+
+#[cfg(feature = "transcode")]
+use crate::image::{Image, TargetFormat};
+
+/// Manifest is saved after this many pages are settled
+const SAVE_EVERY: usize = 16;
+
+/// or when this long has passed since it's saved last
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("gallery {0} is not found")]
+    GalleryNotFound(u32),
+
+    #[error("failed to deserialize manifest {0:?}: {1}")]
+    DeserializeManifest(PathBuf, serde_json::Error),
+
+    #[error("unsupported version of manifest {0:?}: {1}")]
+    UnsupportedManifestVersion(PathBuf, u32),
+
+    #[error("failed to parse template {0:?}: {1}")]
+    ParseTemplate(String, String),
+}
+
+/// Gallery to download
+#[derive(Debug, Clone)]
+pub enum Source {
+    Id(u32),
+    Gallery(model::Gallery),
+}
+
+impl From<u32> for Source {
+    fn from(id: u32) -> Self {
+        Source::Id(id)
+    }
+}
+
+impl From<model::Gallery> for Source {
+    fn from(gallery: model::Gallery) -> Self {
+        Source::Gallery(gallery)
+    }
+}
+
+/// Emitted by [`GalleryDownloader::run`]
+///
+/// `page` starts from 1.
+#[derive(Debug)]
+pub enum Event {
+    Started {
+        id: u32,
+        title: String,
+        pages: usize,
+    },
+    Progress {
+        page: usize,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    PageDone {
+        page: usize,
+        path: PathBuf,
+        ext: ImageExt,
+        size: u64,
+    },
+    /// Already downloaded by previous run, see [`Manifest`]
+    PageSkipped {
+        page: usize,
+        path: PathBuf,
+        ext: ImageExt,
+        size: u64,
+    },
+    PageFailed {
+        page: usize,
+        error: crate::Error,
+    },
+    Finished {
+        succeeded: usize,
+        skipped: usize,
+        failed: usize,
+    },
+    /// Gallery or gg.js couldn't be fetched, no page is downloaded
+    Aborted(crate::Error),
+}
+
+/// Downloads every page of a gallery into `dir`, named by [`Template`]
+///
+/// Progress is saved in [`Manifest`] of the gallery directory every few pages and when finished,
+/// so running again skips pages already downloaded and verified.
+///
+/// ```no_run
+/// # async fn run() {
+/// use futures::StreamExt;
+/// use hitomi_la::download::{Event, GalleryDownloader};
+///
+/// let mut events = GalleryDownloader::new(1234, "./galleries").concurrency(8).run();
+///
+/// while let Some(event) = events.next().await {
+///     if let Event::PageDone { page, path, .. } = event {
+///         println!("{page}: {}", path.display());
+///     }
+/// }
+/// # }
+/// ```
+pub struct GalleryDownloader {
+    source: Source,
+    dir: PathBuf,
+    kind: ImageKind,
+    exts: Vec<ImageExt>,
+    concurrency: usize,
+    gg: Option<Arc<GgCache>>,
+    verify: Verify,
+    template: Template,
+    sidecars: Vec<Sidecar>,
+    #[cfg(feature = "transcode")]
+    transcode: Option<(TargetFormat, u8)>,
+}
+
+impl GalleryDownloader {
+    pub fn new(source: impl Into<Source>, dir: impl Into<PathBuf>) -> Self {
+        GalleryDownloader {
+            source: source.into(),
+            dir: dir.into(),
+            kind: ImageKind::Original,
+            exts: vec![ImageExt::Avif, ImageExt::Webp, ImageExt::Original],
+            concurrency: 4,
+            gg: None,
+            verify: Verify::default(),
+            template: Template::default(),
+            sidecars: Vec::new(),
+            #[cfg(feature = "transcode")]
+            transcode: None,
+        }
+    }
+
+    /// Defaults to [`ImageKind::Original`]
+    pub fn kind(mut self, kind: ImageKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Exts in order of preference, falls back to the next one if hitomi responds 404
+    ///
+    /// Defaults to avif, webp and original.
+    pub fn exts(mut self, exts: impl IntoIterator<Item = ImageExt>) -> Self {
+        self.exts = exts.into_iter().collect();
+        self
+    }
+
+    /// Count of pages downloaded at once, defaults to 4
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Shares gg.js with other downloads, otherwise fetched on [`run`](Self::run)
+    pub fn gg(mut self, gg: Arc<GgCache>) -> Self {
+        self.gg = Some(gg);
+        self
+    }
+
+    /// Defaults to [`Verify::all`]
+    pub fn verify(mut self, verify: Verify) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Names gallery directory and pages, defaults to `{id}/{page}.{ext}`
+    ///
+    /// Pages rendered into the same name get ` (n)` suffix.
+    pub fn template(mut self, template: Template) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Writes metadata files into gallery directory before pages, none by default
+    pub fn sidecars(mut self, sidecars: impl IntoIterator<Item = Sidecar>) -> Self {
+        self.sidecars = sidecars.into_iter().collect();
+        self
+    }
+
+    /// Transcodes each page after downloading, see [`image::transcode`]
+    ///
+    /// AVIF and JXL are skipped as they can't be decoded, so the next ext is downloaded instead.
+    #[cfg(feature = "transcode")]
+    pub fn transcode(mut self, target: TargetFormat, quality: u8) -> Self {
+        self.transcode = Some((target, quality));
+        self
+    }
+
+    /// Starts downloading, which is driven by polling returned stream
+    ///
+    /// Stream ends after [`Event::Finished`] or [`Event::Aborted`].
+    pub fn run(self) -> BoxStream<'static, Event> {
+        let (tx, rx) = mpsc::unbounded();
+
+        let driver = async move {
+            if let Err(err) = self.drive(&tx).await {
+                tx.unbounded_send(Event::Aborted(err)).ok();
+            }
+        };
+
+        // the driver yields nothing, but keeps going while events are polled
+        stream::select(rx, stream::once(driver).filter_map(|_| future::ready(None))).boxed()
+    }
+
+    async fn drive(&self, tx: &mpsc::UnboundedSender<Event>) -> crate::Result<()> {
+        let gallery = match &self.source {
+            Source::Id(id) => gallery::parse(*id)
+                .await?
+                .ok_or(Error::GalleryNotFound(*id))?,
+            Source::Gallery(gallery) => gallery.clone(),
+        };
+
+        let gg = match &self.gg {
+            Some(gg) => gg.clone(),
+            None => Arc::new(GgCache::new(Duration::from_secs(60)).await?),
+        };
+
+        let mut dir = self.dir.join(self.template.gallery_dir(&gallery));
+        let mut previous = load_manifest(&dir).await;
+
+        // another gallery is rendered into the same directory
+        if let Some(other) = previous.as_ref().filter(|m| m.gallery.id != gallery.id) {
+            tracing::debug!(?dir, other = other.gallery.id, "gallery directory collides");
+
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            let name = template::sanitize(&format!("{} ({})", name, gallery.id));
+
+            dir.set_file_name(name);
+            previous = load_manifest(&dir).await;
+        }
+
+        fs::create_dir_all(&dir).await?;
+
+        let manifest = Manifest::resume(gallery.clone(), previous);
+
+        manifest.save(&dir).await?;
+
+        for sidecar in &self.sidecars {
+            sidecar.write(&gallery, &dir).await?;
+        }
+
+        // names of done pages are kept even if they turn out broken
+        let names = manifest
+            .pages
+            .iter()
+            .filter_map(|(page, entry)| Some((entry.file_name.as_ref()?.to_lowercase(), *page)))
+            .collect();
+
+        let run = Run {
+            gallery: &gallery,
+            gg,
+            dir,
+            manifest: Mutex::new(ManifestWriter {
+                manifest,
+                unsaved: 0,
+                saved_at: Instant::now(),
+            }),
+            names: std::sync::Mutex::new(names),
+            tx,
+        };
+
+        tx.unbounded_send(Event::Started {
+            id: gallery.id,
+            title: gallery.title.clone(),
+            pages: gallery.files.len(),
+        })
+        .ok();
+
+        let (succeeded, skipped, failed) = stream::iter(gallery.files.clone())
+            .map(|(page, file)| self.run_page(&run, page, file))
+            .buffer_unordered(self.concurrency)
+            .fold((0, 0, 0), |(succeeded, skipped, failed), outcome| {
+                future::ready(match outcome {
+                    Outcome::Succeeded => (succeeded + 1, skipped, failed),
+                    Outcome::Skipped => (succeeded, skipped + 1, failed),
+                    Outcome::Failed => (succeeded, skipped, failed + 1),
+                })
+            })
+            .await;
+
+        run.manifest.lock().await.save(&run.dir).await;
+
+        tx.unbounded_send(Event::Finished {
+            succeeded,
+            skipped,
+            failed,
+        })
+        .ok();
+
+        Ok(())
+    }
+
+    async fn run_page(&self, run: &Run<'_>, page: usize, file: File) -> Outcome {
+        let done = run.manifest.lock().await.manifest.page(page).cloned();
+
+        if let Some(done) = done {
+            if let Some((path, ext, size)) = self.intact(&file, &run.dir, &done).await {
+                run.tx
+                    .unbounded_send(Event::PageSkipped {
+                        page,
+                        path,
+                        ext,
+                        size,
+                    })
+                    .ok();
+
+                return Outcome::Skipped;
+            }
+        }
+
+        let res = self.page(run, page, &file).await;
+
+        // saved while locked, so writes never interleave
+        let mut writer = run.manifest.lock().await;
+
+        match &res {
+            Ok((path, ext, size)) => {
+                let file_name = path.file_name().unwrap_or_default();
+                let file_name = file_name.to_string_lossy().into_owned();
+
+                writer.manifest.set_done(page, *ext, file_name, *size);
+            }
+            Err(error) => writer.manifest.set_failed(page, error),
+        }
+
+        writer.unsaved += 1;
+
+        if writer.unsaved >= SAVE_EVERY || writer.saved_at.elapsed() >= SAVE_INTERVAL {
+            writer.save(&run.dir).await;
+        }
+
+        drop(writer);
+
+        let (event, outcome) = match res {
+            Ok((path, ext, size)) => (
+                Event::PageDone {
+                    page,
+                    path,
+                    ext,
+                    size,
+                },
+                Outcome::Succeeded,
+            ),
+            Err(error) => (Event::PageFailed { page, error }, Outcome::Failed),
+        };
+
+        run.tx.unbounded_send(event).ok();
+
+        outcome
+    }
+
+    /// Returns saved page if it's done and still passes [`Verify`]
+    async fn intact(
+        &self,
+        file: &File,
+        dir: &Path,
+        entry: &PageEntry,
+    ) -> Option<(PathBuf, ImageExt, u64)> {
+        let (PageStatus::Done, Some(ext), Some(file_name), Some(size)) =
+            (entry.status, entry.ext, &entry.file_name, entry.size)
+        else {
+            return None;
+        };
+
+        let path = dir.join(file_name);
+
+        let res = async {
+            let mut f = fs::File::open(&path).await?;
+
+            if f.metadata().await?.len() != size {
+                return Ok(false);
+            }
+
+            if self.verify == Verify::none() {
+                return Ok(true);
+            }
+
+            let mut verifier = Verifier::new(file, ext, Some(size), self.verify);
+            let mut buf = vec![0; 64 * 1024];
+
+            loop {
+                let n = f.read(&mut buf).await?;
+
+                if n == 0 {
+                    break;
+                }
+
+                verifier.update(&buf[..n]);
+            }
+
+            Ok::<_, std::io::Error>(verifier.finish().is_ok())
+        };
+
+        match res.await {
+            Ok(true) => Some((path, ext, size)),
+            Ok(false) => {
+                tracing::debug!(?path, "saved page is broken, download again");
+                None
+            }
+            Err(err) => {
+                tracing::debug!(?path, %err, "saved page is missing, download again");
+                None
+            }
+        }
+    }
+
+    async fn page(
+        &self,
+        run: &Run<'_>,
+        page: usize,
+        file: &File,
+    ) -> crate::Result<(PathBuf, ImageExt, u64)> {
+        let exts = self.preferred_exts();
+
+        for ext in image::available_exts(file, self.kind, &exts) {
+            let name = self
+                .template
+                .file_name(run.gallery, page, file, file.extension(ext));
+            let path = run.dir.join(run.claim(page, name));
+
+            let progress = |downloaded, total| {
+                run.tx
+                    .unbounded_send(Event::Progress {
+                        page,
+                        downloaded,
+                        total,
+                    })
+                    .ok();
+            };
+
+            let snapshot = run.gg.get().await?;
+
+            let mut res = image::download_to_path_with(
+                file,
+                self.kind,
+                ext,
+                &snapshot,
+                &path,
+                self.verify,
+                progress,
+            )
+            .await;
+
+            // gg.js may be rotated
+            if is_status(&res, &[StatusCode::FORBIDDEN, StatusCode::NOT_FOUND]) {
+                let gg = run.gg.refresh_stale(&snapshot).await?;
+
+                res = image::download_to_path_with(
+                    file,
+                    self.kind,
+                    ext,
+                    &gg,
+                    &path,
+                    self.verify,
+                    progress,
+                )
+                .await;
+            }
+
+            match res {
+                Ok(size) => return self.finish(run, page, file, ext, path, size).await,
+                Err(_) if is_status(&res, &[StatusCode::NOT_FOUND]) => {
+                    tracing::debug!(page, ?ext, "not found, fallback to next ext");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(image::Error::HasNotAnyImage(self.exts.clone()).into())
+    }
+
+    /// Exts to download in order of preference
+    fn preferred_exts(&self) -> Vec<ImageExt> {
+        #[cfg(feature = "transcode")]
+        if self.transcode.is_some() {
+            return self
+                .exts
+                .iter()
+                .copied()
+                .filter(|ext| !matches!(ext, ImageExt::Avif | ImageExt::Jxl))
+                .collect();
+        }
+
+        self.exts.clone()
+    }
+
+    #[cfg(not(feature = "transcode"))]
+    async fn finish(
+        &self,
+        _run: &Run<'_>,
+        _page: usize,
+        _file: &File,
+        ext: ImageExt,
+        path: PathBuf,
+        size: u64,
+    ) -> crate::Result<(PathBuf, ImageExt, u64)> {
+        Ok((path, ext, size))
+    }
+
+    #[cfg(feature = "transcode")]
+    async fn finish(
+        &self,
+        run: &Run<'_>,
+        page: usize,
+        file: &File,
+        ext: ImageExt,
+        path: PathBuf,
+        size: u64,
+    ) -> crate::Result<(PathBuf, ImageExt, u64)> {
+        let Some((target, quality)) = self.transcode else {
+            return Ok((path, ext, size));
+        };
+
+        let image = Image {
+            kind: self.kind,
+            ext,
+            url: path.to_string_lossy().into_owned(),
+            buf: fs::read(&path).await?.into(),
+        };
+
+        let transcoded = image.transcode(target, quality)?;
+
+        let name = self
+            .template
+            .file_name(run.gallery, page, file, transcoded.extension());
+        let transcoded_path = run.dir.join(run.claim(page, name));
+
+        // original is kept until transcoded page is complete
+        let part = image::part_path(&transcoded_path);
+
+        fs::write(&part, &transcoded.buf).await?;
+        fs::rename(&part, &transcoded_path).await?;
+
+        if transcoded_path != path {
+            fs::remove_file(&path).await?;
+        }
+
+        tracing::debug!(page, hash = file.hash, ?target, "transcoded");
+
+        Ok((transcoded_path, transcoded.ext, transcoded.buf.len() as u64))
+    }
+}
+
+/// State shared by pages of a gallery
+struct Run<'a> {
+    gallery: &'a model::Gallery,
+    gg: Arc<GgCache>,
+    dir: PathBuf,
+    manifest: Mutex<ManifestWriter>,
+    /// Lowercased file names to page, as filesystems may be case insensitive
+    names: std::sync::Mutex<HashMap<String, usize>>,
+    tx: &'a mpsc::UnboundedSender<Event>,
+}
+
+impl Run<'_> {
+    /// Returns `name`, or `name (n)` if another page already uses it
+    fn claim(&self, page: usize, name: String) -> String {
+        let mut names = self.names.lock().unwrap();
+
+        let mut claimed = name.clone();
+
+        for n in 2.. {
+            match names.get(&claimed.to_lowercase()) {
+                Some(owner) if *owner != page => claimed = template::with_suffix(&name, n),
+                _ => break,
+            }
+        }
+
+        names.insert(claimed.to_lowercase(), page);
+
+        claimed
+    }
+}
+
+/// Batches saves of manifest, rewriting it for every page is quadratic
+struct ManifestWriter {
+    manifest: Manifest,
+    /// Count of pages settled since last save
+    unsaved: usize,
+    saved_at: Instant,
+}
+
+impl ManifestWriter {
+    async fn save(&mut self, dir: &Path) {
+        if let Err(err) = self.manifest.save(dir).await {
+            tracing::warn!(%err, "failed to save manifest");
+        }
+
+        self.unsaved = 0;
+        self.saved_at = Instant::now();
+    }
+}
+
+async fn load_manifest(dir: &Path) -> Option<Manifest> {
+    Manifest::load(dir).await.unwrap_or_else(|err| {
+        tracing::warn!(%err, "start over, manifest is broken");
+        None
+    })
+}
+
+enum Outcome {
+    Succeeded,
+    Skipped,
+    Failed,
+}
+
+fn is_status<T>(res: &crate::Result<T>, statuses: &[StatusCode]) -> bool {
+    matches!(
+        res,
+        Err(crate::Error::Http(network::http::Error::Status(status))) if statuses.contains(status)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gg::GG, tests::temp_dir};
+
+    use super::*;
+
+    fn gallery() -> model::Gallery {
+        let file = File {
+            has_webp: false,
+            name: "1.jpg".to_owned(),
+            ..crate::tests::file(&"0".repeat(64))
+        };
+
+        model::Gallery {
+            id: 1,
+            files: vec![(1, file)],
+            ..crate::tests::gallery(&[])
+        }
+    }
+
+    async fn download(dir: &Path) -> Vec<Event> {
+        let js = "var gg = { m: function(g) { return 0; }, b: '123/' };";
+        let gg = GgCache::with_gg(GG::from_js(js).unwrap(), Duration::from_secs(60));
+
+        GalleryDownloader::new(gallery(), dir)
+            .exts([ImageExt::Webp])
+            .gg(Arc::new(gg))
+            .verify(Verify::none())
+            .sidecars([Sidecar::Json])
+            .run()
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[tokio::test]
+    async fn download_gallery_without_ext() {
+        let dir = temp_dir("download_gallery_without_ext");
+
+        let events = download(&dir).await;
+
+        let manifest = Manifest::load(dir.join("1")).await.unwrap().unwrap();
+        let sidecar = fs::try_exists(dir.join("1/gallery.json")).await.unwrap();
+
+        fs::remove_dir_all(&dir).await.ok();
+
+        assert!(matches!(
+            events[0],
+            Event::Started {
+                id: 1,
+                pages: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[1],
+            Event::PageFailed {
+                page: 1,
+                error: crate::Error::Image(image::Error::HasNotAnyImage(_)),
+            }
+        ));
+        assert!(matches!(
+            events[2],
+            Event::Finished {
+                succeeded: 0,
+                skipped: 0,
+                failed: 1
+            }
+        ));
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(manifest.page(1).unwrap().status, PageStatus::Failed);
+        assert!(sidecar);
+    }
+
+    #[tokio::test]
+    async fn skip_downloaded_page() {
+        let dir = temp_dir("skip_downloaded_page");
+        let gallery_dir = dir.join("1");
+
+        fs::create_dir_all(&gallery_dir).await.unwrap();
+        fs::write(gallery_dir.join("1.webp"), b"webp")
+            .await
+            .unwrap();
+
+        let mut manifest = Manifest::new(gallery());
+        manifest.set_done(1, ImageExt::Webp, "1.webp".to_owned(), 4);
+        manifest.save(&gallery_dir).await.unwrap();
+
+        let skipped = download(&dir).await;
+
+        // broken page is downloaded again
+        fs::write(gallery_dir.join("1.webp"), b"web").await.unwrap();
+
+        let retried = download(&dir).await;
+
+        fs::remove_dir_all(&dir).await.ok();
+
+        assert!(matches!(
+            &skipped[1],
+            Event::PageSkipped { page: 1, ext: ImageExt::Webp, size: 4, path } if path.ends_with("1/1.webp")
+        ));
+        assert!(matches!(retried[1], Event::PageFailed { page: 1, .. }));
+    }
+
+    #[cfg(feature = "transcode")]
+    #[test]
+    fn transcode_without_avif() {
+        let downloader = GalleryDownloader::new(1, "./galleries");
+
+        assert_eq!(
+            downloader.preferred_exts(),
+            [ImageExt::Avif, ImageExt::Webp, ImageExt::Original]
+        );
+
+        let downloader = downloader.transcode(TargetFormat::Png, 90);
+
+        assert_eq!(
+            downloader.preferred_exts(),
+            [ImageExt::Webp, ImageExt::Original]
+        );
+    }
+
+    #[cfg(feature = "transcode")]
+    #[tokio::test]
+    async fn transcode_avif_only_gallery() {
+        let dir = temp_dir("transcode_avif_only_gallery");
+
+        let mut gallery = gallery();
+        gallery.files[0].1.name = "1".to_owned();
+
+        let js = "var gg = { m: function(g) { return 0; }, b: '123/' };";
+        let gg = GgCache::with_gg(GG::from_js(js).unwrap(), Duration::from_secs(60));
+
+        // avif is never downloaded, as it can't be transcoded
+        let events = GalleryDownloader::new(gallery, &dir)
+            .gg(Arc::new(gg))
+            .transcode(TargetFormat::Png, 90)
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        fs::remove_dir_all(&dir).await.ok();
+
+        assert!(matches!(
+            events[1],
+            Event::PageFailed {
+                page: 1,
+                error: crate::Error::Image(image::Error::HasNotAnyImage(_)),
+            }
+        ));
+    }
+}
@@ -0,0 +1,495 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::model::{File, Gallery, TagKind};
+
+use super::Error;
+
+/// Max length of a file name in bytes on most filesystems
+const MAX_NAME_LEN: usize = 255;
+
+/// Extensions longer than this are truncated with the rest of name
+const MAX_EXT_LEN: usize = 16;
+
+/// Names which Windows reserves for devices, with or without extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Path of downloaded page, e.g. `{artist} - {title} [{id}]/{page:03}.{ext}`
+///
+/// Segments separated by `/` but the last one are directories of a gallery,
+/// which can use only gallery fields. The last one is file name of a page.
+///
+/// Gallery fields:
+/// - `id`, `title`, `kind`, `language`
+/// - `date`, `published`: `YYYY-MM-DD` of [`Gallery::date_added`] and [`Gallery::date_published`]
+/// - `artist`, `group`, `series`, `character`, `female`, `male`, `misc`: tag names joined by `, `
+/// - `tags`: every tag name joined by `, `
+///
+/// File fields:
+/// - `page`, `hash`, `width`, `height`
+/// - `name`: [`File::name`] without extension
+/// - `ext`: extension actually saved, e.g. `avif`, `jpg`
+///
+/// `{field:0N}` pads with zeros to N chars, `{field:.N}` keeps first N chars.
+/// `{{` and `}}` are literal braces.
+///
+/// Each segment is sanitized by [`sanitize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    dirs: Vec<Vec<Token>>,
+    file: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    Field {
+        field: Field,
+        pad: Option<usize>,
+        max: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Title,
+    Kind,
+    Language,
+    Date,
+    Published,
+    Tag(TagKind),
+    Tags,
+    Page,
+    Hash,
+    Width,
+    Height,
+    Name,
+    Ext,
+}
+
+impl Field {
+    fn parse(s: &str) -> Option<Self> {
+        let field = match s {
+            "id" => Field::Id,
+            "title" => Field::Title,
+            "kind" => Field::Kind,
+            "language" => Field::Language,
+            "date" => Field::Date,
+            "published" => Field::Published,
+            "artist" => Field::Tag(TagKind::Artist),
+            "group" => Field::Tag(TagKind::Group),
+            "series" => Field::Tag(TagKind::Series),
+            "character" => Field::Tag(TagKind::Character),
+            "female" => Field::Tag(TagKind::Female),
+            "male" => Field::Tag(TagKind::Male),
+            "misc" => Field::Tag(TagKind::Misc),
+            "tags" => Field::Tags,
+            "page" => Field::Page,
+            "hash" => Field::Hash,
+            "width" => Field::Width,
+            "height" => Field::Height,
+            "name" => Field::Name,
+            "ext" => Field::Ext,
+            _ => return None,
+        };
+
+        Some(field)
+    }
+
+    fn is_file(&self) -> bool {
+        matches!(
+            self,
+            Field::Page | Field::Hash | Field::Width | Field::Height | Field::Name | Field::Ext
+        )
+    }
+}
+
+/// Values of file fields
+struct Page<'a> {
+    page: usize,
+    file: &'a File,
+    extension: &'a str,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let err = |reason: String| Error::ParseTemplate(s.to_owned(), reason);
+
+        let mut segments = s
+            .split('/')
+            .map(|segment| parse_segment(segment).map_err(err))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let file = segments.pop().unwrap_or_default();
+
+        if file.is_empty() || segments.iter().any(Vec::is_empty) {
+            return Err(err("empty segment".to_owned()));
+        }
+
+        let uses_file_field = |tokens: &Vec<Token>| {
+            tokens
+                .iter()
+                .any(|token| matches!(token, Token::Field { field, .. } if field.is_file()))
+        };
+
+        if segments.iter().any(uses_file_field) {
+            return Err(err("file field in directory".to_owned()));
+        }
+
+        Ok(Template {
+            source: s.to_owned(),
+            dirs: segments,
+            file,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Directory of `gallery`, relative to output directory
+    pub fn gallery_dir(&self, gallery: &Gallery) -> PathBuf {
+        self.dirs
+            .iter()
+            .map(|tokens| sanitize(&render(tokens, gallery, None)))
+            .collect()
+    }
+
+    /// File name of `page` saved as `extension`, in [`gallery_dir`](Self::gallery_dir)
+    pub fn file_name(
+        &self,
+        gallery: &Gallery,
+        page: usize,
+        file: &File,
+        extension: &str,
+    ) -> String {
+        let page = Page {
+            page,
+            file,
+            extension,
+        };
+
+        sanitize(&render(&self.file, gallery, Some(&page)))
+    }
+}
+
+/// `{id}/{page}.{ext}`
+impl Default for Template {
+    fn default() -> Self {
+        Self::parse("{id}/{page}.{ext}").unwrap()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+fn parse_segment(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("unclosed `{`".to_owned()),
+                    }
+                }
+
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+
+                tokens.push(parse_field(&spec)?);
+            }
+            '}' => return Err("unmatched `}`".to_owned()),
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_field(spec: &str) -> Result<Token, String> {
+    let (name, format) = match spec.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (spec, None),
+    };
+
+    let field = Field::parse(name.trim()).ok_or_else(|| format!("unknown field `{name}`"))?;
+
+    let invalid = || format!("invalid format `{spec}`");
+
+    let (pad, max) = match format {
+        None => (None, None),
+        Some(format) => {
+            if let Some(width) = format.strip_prefix('0') {
+                (Some(width.parse().map_err(|_| invalid())?), None)
+            } else if let Some(len) = format.strip_prefix('.') {
+                (None, Some(len.parse().map_err(|_| invalid())?))
+            } else {
+                return Err(invalid());
+            }
+        }
+    };
+
+    Ok(Token::Field { field, pad, max })
+}
+
+fn render(tokens: &[Token], gallery: &Gallery, page: Option<&Page>) -> String {
+    let mut s = String::new();
+
+    for token in tokens {
+        match token {
+            Token::Literal(literal) => s.push_str(literal),
+            Token::Field { field, pad, max } => {
+                let mut value = value(*field, gallery, page);
+
+                if let Some(pad) = pad {
+                    let len = value.chars().count();
+
+                    if len < *pad {
+                        value.insert_str(0, &"0".repeat(pad - len));
+                    }
+                }
+
+                if let Some(max) = max {
+                    value = value.chars().take(*max).collect();
+                }
+
+                s.push_str(&value);
+            }
+        }
+    }
+
+    s
+}
+
+fn value(field: Field, gallery: &Gallery, page: Option<&Page>) -> String {
+    let join = |names: &mut dyn Iterator<Item = &str>| names.collect::<Vec<_>>().join(", ");
+
+    match (field, page) {
+        (Field::Id, _) => gallery.id.to_string(),
+        (Field::Title, _) => gallery.title.clone(),
+        (Field::Kind, _) => gallery.kind.clone(),
+        (Field::Language, _) => gallery.language.clone().unwrap_or_default(),
+        (Field::Date, _) => gallery.date_added.format("%Y-%m-%d").to_string(),
+        (Field::Published, _) => gallery
+            .date_published
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        (Field::Tag(kind), _) => join(&mut gallery.tag_names(kind)),
+        (Field::Tags, _) => join(&mut gallery.tags.iter().map(|tag| tag.name.as_str())),
+        (Field::Page, Some(page)) => page.page.to_string(),
+        (Field::Hash, Some(page)) => page.file.hash.clone(),
+        (Field::Width, Some(page)) => page.file.width.to_string(),
+        (Field::Height, Some(page)) => page.file.height.to_string(),
+        (Field::Name, Some(page)) => split_ext(&page.file.name).0.to_owned(),
+        (Field::Ext, Some(page)) => page.extension.to_owned(),
+        // rejected by `Template::parse`
+        (_, None) => String::new(),
+    }
+}
+
+/// Makes `name` a valid file name on common filesystems
+///
+/// - normalizes to NFC, as macOS does
+/// - replaces `/ \ : * ? " < > |` and control characters with `_`
+/// - trims whitespaces and trailing dots, which Windows drops
+/// - appends `_` to names Windows reserves, e.g. `CON`, `nul.txt`
+/// - truncates to 255 bytes, keeping extension
+/// - replaces empty name with `_`
+pub fn sanitize(name: &str) -> String {
+    let mut s = name
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    s = s.trim().trim_end_matches(['.', ' ']).to_owned();
+
+    if s.is_empty() || s.chars().all(|c| c == '.') {
+        return "_".to_owned();
+    }
+
+    let (stem, ext) = split_ext(&s);
+
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.trim_end().eq_ignore_ascii_case(reserved))
+    {
+        s = format!("{stem}_{ext}");
+    }
+
+    truncate(&s, MAX_NAME_LEN)
+}
+
+/// Splits `name` into stem and extension with leading dot
+pub(crate) fn split_ext(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i) if i > 0 && name.len() - i <= MAX_EXT_LEN + 1 => name.split_at(i),
+        _ => (name, ""),
+    }
+}
+
+fn truncate(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_owned();
+    }
+
+    let (stem, ext) = split_ext(name);
+
+    let mut end = max.saturating_sub(ext.len());
+
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let stem = stem[..end].trim_end_matches(['.', ' ']);
+
+    format!("{stem}{ext}")
+}
+
+/// Appends ` (n)` to stem of `name`, e.g. `1 (2).avif`
+pub(crate) fn with_suffix(name: &str, n: usize) -> String {
+    let suffix = format!(" ({n})");
+
+    let (stem, ext) = split_ext(name);
+    let stem = truncate(stem, MAX_NAME_LEN - ext.len() - suffix.len());
+
+    format!("{stem}{suffix}{ext}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Tag;
+
+    use super::*;
+
+    fn gallery() -> Gallery {
+        Gallery {
+            title: "A: Title?".to_owned(),
+            language: Some("korean".to_owned()),
+            tags: [
+                (TagKind::Artist, "foo"),
+                (TagKind::Artist, "bar"),
+                (TagKind::Female, "baz"),
+            ]
+            .into_iter()
+            .map(|(kind, name)| Tag {
+                kind,
+                name: name.to_owned(),
+            })
+            .collect(),
+            ..crate::tests::gallery(&[])
+        }
+    }
+
+    #[test]
+    fn render_template() {
+        let file = File {
+            name: "01.jpg".to_owned(),
+            ..crate::tests::file("abc")
+        };
+
+        let template =
+            Template::parse("{artist} - {title} [{id}]/{{{page:03}}} {name}.{ext}").unwrap();
+
+        assert_eq!(
+            template.gallery_dir(&gallery()),
+            PathBuf::from("foo, bar - A_ Title_ [123]")
+        );
+        assert_eq!(
+            template.file_name(&gallery(), 7, &file, "avif"),
+            "{007} 01.avif"
+        );
+
+        let template = Template::parse("{date}/{title:.4}{published}/{page}.{ext}").unwrap();
+
+        assert_eq!(
+            template.gallery_dir(&gallery()),
+            ["2024-01-02", "A_ T"].iter().collect::<PathBuf>()
+        );
+
+        let default = Template::default();
+
+        assert_eq!(default.gallery_dir(&gallery()), PathBuf::from("123"));
+        assert_eq!(default.file_name(&gallery(), 1, &file, "jpg"), "1.jpg");
+    }
+
+    #[test]
+    fn parse_invalid_template() {
+        for s in [
+            "{id}/{unknown}",
+            "{page}/{page}.{ext}",
+            "{id}/{page",
+            "{id}/page}",
+            "{id}/{page:3}",
+            "{id}//{page}",
+            "{id}/",
+        ] {
+            assert!(Template::parse(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn sanitize_name() {
+        assert_eq!(sanitize("a/b\\c:d*e?f\"g<h>i|j\n"), "a_b_c_d_e_f_g_h_i_j_");
+        assert_eq!(sanitize(" title. "), "title");
+        assert_eq!(sanitize("..."), "_");
+        assert_eq!(sanitize(""), "_");
+        assert_eq!(sanitize("con"), "con_");
+        assert_eq!(sanitize("NUL.txt"), "NUL_.txt");
+        assert_eq!(sanitize("console"), "console");
+
+        // decomposed `가` is composed
+        assert_eq!(sanitize("\u{1100}\u{1161}"), "\u{ac00}");
+
+        let long = format!("{}.avif", "가".repeat(100));
+        let truncated = sanitize(&long);
+
+        assert!(truncated.len() <= MAX_NAME_LEN);
+        assert!(truncated.ends_with("가.avif"));
+
+        assert_eq!(with_suffix("1.avif", 2), "1 (2).avif");
+        assert_eq!(with_suffix("1", 3), "1 (3)");
+        assert!(with_suffix(&truncated, 2).len() <= MAX_NAME_LEN);
+    }
+}
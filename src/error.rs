@@ -21,6 +21,15 @@ pub enum Error {
 
     #[error("GG: {0}")]
     GG(#[from] crate::gg::Error),
+
+    #[error("Video: {0}")]
+    Video(#[from] crate::video::Error),
+
+    #[error("Download: {0}")]
+    Download(#[from] crate::download::Error),
+
+    #[error("Export: {0}")]
+    Export(#[from] crate::export::Error),
 }
 
 impl From<reqwest::Error> for Error {
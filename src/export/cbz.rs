@@ -0,0 +1,131 @@
+//! Comic book ZIP archive, pages are stored without compression
+
+use std::{
+    fs,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use zip::ZipWriter;
+
+use crate::model::Gallery;
+
+use super::{comic_info, manifest_pages, write_file, zip_options, Error};
+
+/// Writes `pages` of `gallery` into `writer` in the given order
+///
+/// `pages` are (page, path of downloaded image), each is copied into archive
+/// as `{page}.{extension}` zero-padded to sort in order, with `ComicInfo.xml`.
+pub fn write_pages<W: Write + Seek>(
+    writer: W,
+    gallery: &Gallery,
+    pages: &[(usize, PathBuf)],
+) -> crate::Result<W> {
+    if pages.is_empty() {
+        return Err(Error::Empty.into());
+    }
+
+    let options = zip_options(gallery);
+
+    let mut zip = ZipWriter::new(writer);
+
+    let numbers = pages.iter().map(|(page, _)| *page).collect::<Vec<_>>();
+
+    zip.start_file("ComicInfo.xml", options)
+        .map_err(Error::from)?;
+    zip.write_all(comic_info(gallery, &numbers).as_bytes())?;
+
+    let width = pages.len().to_string().len();
+
+    for (i, (page, path)) in pages.iter().enumerate() {
+        let mut f = fs::File::open(path)?;
+
+        let len = f.metadata()?.len();
+
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .unwrap_or_default();
+
+        let name = format!("{:0width$}.{}", i + 1, extension);
+
+        tracing::trace!(page, name, len, "add page");
+
+        zip.start_file(name, options.large_file(len >= u32::MAX as u64))
+            .map_err(Error::from)?;
+
+        io::copy(&mut f, &mut zip)?;
+    }
+
+    Ok(zip.finish().map_err(Error::from)?)
+}
+
+/// Writes `pages` of `gallery` into `out`, see [`write_pages`]
+///
+/// Written to a part file next to `out` and renamed, so `out` is never partial.
+pub async fn write(
+    gallery: &Gallery,
+    pages: Vec<(usize, PathBuf)>,
+    out: impl AsRef<Path>,
+) -> crate::Result<()> {
+    let gallery = gallery.clone();
+
+    write_file(out.as_ref(), move |f| write_pages(f, &gallery, &pages)).await
+}
+
+/// Writes pages downloaded into `dir` by [`GalleryDownloader`](crate::download::GalleryDownloader)
+///
+/// See [`manifest_pages`](super::manifest_pages) for errors.
+pub async fn from_manifest(dir: impl AsRef<Path>, out: impl AsRef<Path>) -> crate::Result<()> {
+    let (gallery, pages) = manifest_pages(dir.as_ref()).await?;
+
+    write(&gallery, pages, out).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::{CompressionMethod, ZipArchive};
+
+    use crate::{export::tests::gallery, tests::temp_dir};
+
+    use super::*;
+
+    #[test]
+    fn write_cbz() {
+        let dir = temp_dir("write_cbz");
+
+        let pages = (1..=10)
+            .map(|page| {
+                let path = dir.join(format!("{page}.webp"));
+                fs::write(&path, format!("page {page}")).unwrap();
+                (page, path)
+            })
+            .collect::<Vec<_>>();
+
+        let buf = write_pages(Cursor::new(Vec::new()), &gallery(), &pages)
+            .unwrap()
+            .into_inner();
+
+        fs::remove_dir_all(&dir).ok();
+
+        let mut zip = ZipArchive::new(Cursor::new(buf)).unwrap();
+
+        let names = zip.file_names().map(str::to_owned).collect::<Vec<_>>();
+
+        assert_eq!(names.len(), 11);
+        assert_eq!(names[0], "ComicInfo.xml");
+        assert_eq!(names[1], "01.webp");
+        assert_eq!(names[10], "10.webp");
+
+        let mut page = zip.by_name("02.webp").unwrap();
+
+        assert_eq!(page.compression(), CompressionMethod::Stored);
+
+        let mut s = String::new();
+        page.read_to_string(&mut s).unwrap();
+
+        assert_eq!(s, "page 2");
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::Write;
+
+use chrono::Datelike;
+use itertools::Itertools;
+
+use crate::model::{Gallery, TagKind};
+
+use super::escape_xml;
+
+/// Renders `ComicInfo.xml` of `gallery`, read by Komga, Kavita and ComicRack
+///
+/// `pages` are pages packaged in order, which start from 1.
+pub fn comic_info(gallery: &Gallery, pages: &[usize]) -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        "\n",
+        r#"<ComicInfo xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">"#,
+        "\n",
+    ));
+
+    let mut element = |name: &str, value: &str| {
+        if !value.is_empty() {
+            writeln!(xml, "  <{name}>{}</{name}>", escape_xml(value)).unwrap();
+        }
+    };
+
+    let names = |kind| gallery.tag_names(kind).join(", ");
+
+    let date = gallery.date_published.unwrap_or(gallery.date_added);
+
+    let tags = gallery
+        .tags
+        .iter()
+        .filter(|tag| matches!(tag.kind, TagKind::Female | TagKind::Male | TagKind::Misc))
+        .map(|tag| tag.name.as_str())
+        .join(", ");
+
+    element("Title", &gallery.title);
+    element("Year", &date.year().to_string());
+    element("Month", &date.month().to_string());
+    element("Day", &date.day().to_string());
+    element("Writer", &names(TagKind::Artist));
+    element("Genre", &gallery.kind);
+    element("Tags", &tags);
+    element("Web", &gallery.url());
+    element("PageCount", &pages.len().to_string());
+    element("LanguageISO", gallery.language_code().unwrap_or_default());
+    element("Characters", &names(TagKind::Character));
+    element("Teams", &names(TagKind::Group));
+
+    xml.push_str("  <Pages>\n");
+
+    for (i, page) in pages.iter().enumerate() {
+        let file = gallery
+            .files
+            .iter()
+            .find(|(p, _)| p == page)
+            .map(|(_, file)| file);
+
+        write!(xml, r#"    <Page Image="{i}""#).unwrap();
+
+        if i == 0 {
+            xml.push_str(r#" Type="FrontCover""#);
+        }
+
+        if let Some(file) = file {
+            write!(
+                xml,
+                r#" ImageWidth="{}" ImageHeight="{}""#,
+                file.width, file.height
+            )
+            .unwrap();
+        }
+
+        xml.push_str(" />\n");
+    }
+
+    xml.push_str("  </Pages>\n</ComicInfo>\n");
+
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::{gallery, tags};
+
+    use super::*;
+
+    #[test]
+    fn render_comic_info() {
+        let gallery = Gallery {
+            title: "Tom & Jerry <1>".to_owned(),
+            kind: "manga".to_owned(),
+            language: Some("japanese".to_owned()),
+            tags: tags(&[
+                (TagKind::Artist, "foo"),
+                (TagKind::Artist, "bar"),
+                (TagKind::Female, "baz"),
+                (TagKind::Group, "qux"),
+            ]),
+            date_published: None,
+            ..gallery()
+        };
+
+        let xml = comic_info(&gallery, &[1, 2]);
+
+        for element in [
+            "<Title>Tom &amp; Jerry &lt;1&gt;</Title>",
+            "<Year>2024</Year>",
+            "<Month>1</Month>",
+            "<Day>2</Day>",
+            "<Writer>foo, bar</Writer>",
+            "<Genre>manga</Genre>",
+            "<Tags>baz</Tags>",
+            "<Web>https://hitomi.la/galleries/123.html</Web>",
+            "<PageCount>2</PageCount>",
+            "<LanguageISO>ja</LanguageISO>",
+            "<Teams>qux</Teams>",
+            r#"<Page Image="0" Type="FrontCover" ImageWidth="800" ImageHeight="1200" />"#,
+            r#"<Page Image="1" ImageWidth="800" ImageHeight="1200" />"#,
+        ] {
+            assert!(xml.contains(element), "{element}\n{xml}");
+        }
+
+        assert!(!xml.contains("<Characters>"));
+    }
+}
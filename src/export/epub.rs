@@ -0,0 +1,521 @@
+//! EPUB 3 fixed-layout book, one XHTML page per image
+//!
+//! Pages must be in core media types of EPUB, which are JPEG, PNG, GIF and WebP.
+//! AVIF and JXL have no fallback, so download WebP or originals for EPUB.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{
+    image::{self, ImageFormat},
+    model::{Gallery, TagKind},
+};
+
+use super::{check_formats, escape_xml, manifest_pages, write_file, zip_options, Error};
+
+/// Viewport of page whose dimensions are unknown, ratio of A4
+const DEFAULT_SIZE: (u32, u32) = (1000, 1414);
+
+const CORE_MEDIA_TYPES: [ImageFormat; 4] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Webp,
+];
+
+/// Layout of book
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Options {
+    pub direction: Direction,
+    pub spread: Spread,
+}
+
+impl Options {
+    /// Direction of `gallery` with default spread, see [`Direction::of`]
+    pub fn of(gallery: &Gallery) -> Self {
+        Options {
+            direction: Direction::of(gallery),
+            ..Default::default()
+        }
+    }
+}
+
+/// Order of pages when turning them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Left to right, e.g. western comics
+    #[default]
+    Ltr,
+    /// Right to left, e.g. Japanese manga
+    Rtl,
+}
+
+impl Direction {
+    /// Right to left for Japanese gallery, otherwise left to right
+    pub fn of(gallery: &Gallery) -> Self {
+        match gallery.language.as_deref() {
+            Some("japanese") => Direction::Rtl,
+            _ => Direction::Ltr,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Ltr => "ltr",
+            Direction::Rtl => "rtl",
+        }
+    }
+}
+
+/// When two pages are shown side by side, `rendition:spread`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Spread {
+    /// Always one page
+    None,
+    /// Two pages in landscape orientation
+    #[default]
+    Landscape,
+    /// Two pages in both orientations
+    Both,
+    /// Left to reader
+    Auto,
+}
+
+impl Spread {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Spread::None => "none",
+            Spread::Landscape => "landscape",
+            Spread::Both => "both",
+            Spread::Auto => "auto",
+        }
+    }
+}
+
+struct Page {
+    /// `images/{n}.{extension}`, relative to `OEBPS`
+    image: String,
+    mime_type: &'static str,
+    width: u32,
+    height: u32,
+}
+
+/// Writes `pages` of `gallery` into `writer` as a book
+///
+/// `pages` are (page, path of downloaded image) in the given order.
+/// The first page becomes the cover.
+///
+/// ## Errors
+///
+/// - [`Error::UnsupportedFormat`] if any page isn't in core media types, e.g. AVIF
+pub fn write_pages<W: Write + Seek>(
+    writer: W,
+    gallery: &Gallery,
+    pages: &[(usize, PathBuf)],
+    options: Options,
+) -> crate::Result<W> {
+    if pages.is_empty() {
+        return Err(Error::Empty.into());
+    }
+
+    check_formats(pages, &CORE_MEDIA_TYPES)?;
+
+    let file_options = zip_options(gallery);
+
+    let mut zip = ZipWriter::new(writer);
+
+    // must be the first entry and uncompressed
+    add(&mut zip, "mimetype", file_options, b"application/epub+zip")?;
+    add(
+        &mut zip,
+        "META-INF/container.xml",
+        file_options,
+        CONTAINER.as_bytes(),
+    )?;
+
+    let width = pages.len().to_string().len();
+
+    let mut entries = Vec::with_capacity(pages.len());
+
+    for (i, (page, path)) in pages.iter().enumerate() {
+        let n = i + 1;
+
+        // only one page is held in memory
+        let buf = fs::read(path)?;
+
+        let info = image::probe(&buf);
+
+        // changed after checked
+        let format = match image::sniff(&buf) {
+            Some(format) if CORE_MEDIA_TYPES.contains(&format) => format,
+            format => return Err(Error::UnsupportedFormat(vec![(*page, format)]).into()),
+        };
+
+        let file = gallery
+            .files
+            .iter()
+            .find(|(p, _)| p == page)
+            .map(|(_, file)| file);
+
+        let (w, h) = match (info, file) {
+            (Some(info), _) => (info.width, info.height),
+            (None, Some(file)) if file.width > 0 && file.height > 0 => {
+                (file.width as u32, file.height as u32)
+            }
+            _ => {
+                tracing::debug!(page, "unknown dimensions, use default");
+                DEFAULT_SIZE
+            }
+        };
+
+        let entry = Page {
+            image: format!("images/{:0width$}.{}", n, extension(path)),
+            mime_type: format.mime_type(),
+            width: w,
+            height: h,
+        };
+
+        add(
+            &mut zip,
+            &format!("OEBPS/{}", entry.image),
+            file_options,
+            &buf,
+        )?;
+
+        let xhtml = page_xhtml(&gallery.title, n, &entry);
+
+        add(
+            &mut zip,
+            &format!("OEBPS/pages/{:0width$}.xhtml", n),
+            file_options,
+            xhtml.as_bytes(),
+        )?;
+
+        entries.push(entry);
+    }
+
+    add(
+        &mut zip,
+        "OEBPS/nav.xhtml",
+        file_options,
+        nav_xhtml(gallery, entries.len(), width).as_bytes(),
+    )?;
+
+    let opf = content_opf(gallery, &entries, width, options);
+
+    add(&mut zip, "OEBPS/content.opf", file_options, opf.as_bytes())?;
+
+    Ok(zip.finish().map_err(Error::from)?)
+}
+
+/// Writes `pages` of `gallery` into `out`, see [`write_pages`]
+///
+/// Written to a part file next to `out` and renamed, so `out` is never partial.
+pub async fn write(
+    gallery: &Gallery,
+    pages: Vec<(usize, PathBuf)>,
+    out: impl AsRef<Path>,
+    options: Options,
+) -> crate::Result<()> {
+    let gallery = gallery.clone();
+
+    write_file(out.as_ref(), move |f| {
+        write_pages(f, &gallery, &pages, options)
+    })
+    .await
+}
+
+/// Writes pages downloaded into `dir` by [`GalleryDownloader`](crate::download::GalleryDownloader)
+///
+/// See [`manifest_pages`](super::manifest_pages) and [`write_pages`] for errors.
+pub async fn from_manifest(
+    dir: impl AsRef<Path>,
+    out: impl AsRef<Path>,
+    options: Options,
+) -> crate::Result<()> {
+    let (gallery, pages) = manifest_pages(dir.as_ref()).await?;
+
+    write(&gallery, pages, out, options).await
+}
+
+fn add<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    options: SimpleFileOptions,
+    buf: &[u8],
+) -> crate::Result<()> {
+    zip.start_file(name, options).map_err(Error::from)?;
+    zip.write_all(buf)?;
+
+    Ok(())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml" />
+  </rootfiles>
+</container>
+"#;
+
+fn page_xhtml(title: &str, n: usize, page: &Page) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title} - {n}</title>
+  <meta name="viewport" content="width={width}, height={height}" />
+  <style>html, body {{ margin: 0; padding: 0; }} img {{ display: block; width: 100%; height: 100%; }}</style>
+</head>
+<body>
+  <img src="../{image}" alt="{n}" />
+</body>
+</html>
+"#,
+        title = escape_xml(title),
+        width = page.width,
+        height = page.height,
+        image = page.image,
+    )
+}
+
+fn nav_xhtml(gallery: &Gallery, len: usize, width: usize) -> String {
+    let page_list = (1..=len)
+        .map(|n| format!(r#"      <li><a href="pages/{n:0width$}.xhtml">{n}</a></li>"#))
+        .join("\n");
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+</head>
+<body>
+  <nav epub:type="toc" id="toc">
+    <ol>
+      <li><a href="pages/{first:0width$}.xhtml">{title}</a></li>
+    </ol>
+  </nav>
+  <nav epub:type="page-list" id="page-list" hidden="">
+    <ol>
+{page_list}
+    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        title = escape_xml(&gallery.title),
+        first = 1,
+    )
+}
+
+fn content_opf(gallery: &Gallery, pages: &[Page], width: usize, options: Options) -> String {
+    let mut metadata = String::new();
+
+    let mut element = |name: &str, value: &str| {
+        writeln!(metadata, "    <{name}>{}</{name}>", escape_xml(value)).unwrap();
+    };
+
+    element("dc:title", &gallery.title);
+    element("dc:language", gallery.language_code().unwrap_or("und"));
+
+    for artist in gallery.tag_names(TagKind::Artist) {
+        element("dc:creator", artist);
+    }
+
+    for tag in gallery.tags.iter().map(|tag| tag.name.as_str()).unique() {
+        element("dc:subject", tag);
+    }
+
+    let date = gallery.date_published.unwrap_or(gallery.date_added);
+
+    element("dc:date", &date.format("%Y-%m-%d").to_string());
+    element("dc:source", &gallery.url());
+
+    let mut manifest = String::new();
+    let mut spine = String::new();
+
+    for (i, page) in pages.iter().enumerate() {
+        let n = i + 1;
+
+        let properties = if i == 0 {
+            r#" properties="cover-image""#
+        } else {
+            ""
+        };
+
+        writeln!(
+            manifest,
+            r#"    <item id="image-{n}" href="{}" media-type="{}"{properties} />"#,
+            page.image, page.mime_type,
+        )
+        .unwrap();
+        writeln!(
+            manifest,
+            r#"    <item id="page-{n}" href="pages/{n:0width$}.xhtml" media-type="application/xhtml+xml" />"#,
+        )
+        .unwrap();
+        writeln!(spine, r#"    <itemref idref="page-{n}" />"#).unwrap();
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">{identifier}</dc:identifier>
+{metadata}    <meta property="dcterms:modified">{modified}</meta>
+    <meta property="rendition:layout">pre-paginated</meta>
+    <meta property="rendition:orientation">auto</meta>
+    <meta property="rendition:spread">{spread}</meta>
+    <meta name="cover" content="image-1" />
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+{manifest}  </manifest>
+  <spine page-progression-direction="{direction}">
+{spine}  </spine>
+</package>
+"#,
+        identifier = escape_xml(&gallery.url()),
+        modified = gallery.date_added.format("%Y-%m-%dT%H:%M:%SZ"),
+        direction = options.direction.as_str(),
+        spread = options.spread.as_str(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::{CompressionMethod, ZipArchive};
+
+    use crate::{
+        export::tests::{gallery, AVIF},
+        tests::temp_dir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn write_epub() {
+        let dir = temp_dir("write_epub");
+
+        // 1x1 PNG
+        let png = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 0x0d, b'I', b'H', b'D', b'R',
+            0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0,
+        ];
+
+        let pages = (1..=2)
+            .map(|page| {
+                let path = dir.join(format!("{page}.png"));
+                fs::write(&path, png).unwrap();
+                (page, path)
+            })
+            .collect::<Vec<_>>();
+
+        let gallery = Gallery {
+            title: "A & B".to_owned(),
+            language: Some("japanese".to_owned()),
+            ..gallery()
+        };
+
+        let buf = write_pages(
+            Cursor::new(Vec::new()),
+            &gallery,
+            &pages,
+            Options {
+                spread: Spread::Both,
+                ..Options::of(&gallery)
+            },
+        )
+        .unwrap()
+        .into_inner();
+
+        fs::remove_dir_all(&dir).ok();
+
+        let mut zip = ZipArchive::new(Cursor::new(buf)).unwrap();
+
+        let mimetype = zip.by_index(0).unwrap();
+
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        assert!(mimetype.extra_data().unwrap_or_default().is_empty());
+
+        drop(mimetype);
+
+        let read = |zip: &mut ZipArchive<_>, name| {
+            let mut s = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut s).unwrap();
+            s
+        };
+
+        let opf = read(&mut zip, "OEBPS/content.opf");
+
+        for element in [
+            r#"<dc:identifier id="id">https://hitomi.la/galleries/123.html</dc:identifier>"#,
+            "<dc:title>A &amp; B</dc:title>",
+            "<dc:language>ja</dc:language>",
+            "<dc:creator>foo bar</dc:creator>",
+            "<dc:date>2023-12-31</dc:date>",
+            r#"<meta property="dcterms:modified">2024-01-02T03:04:05Z</meta>"#,
+            r#"<item id="image-1" href="images/1.png" media-type="image/png" properties="cover-image" />"#,
+            r#"<spine page-progression-direction="rtl">"#,
+            r#"<meta property="rendition:spread">both</meta>"#,
+            r#"<itemref idref="page-2" />"#,
+        ] {
+            assert!(opf.contains(element), "{element}\n{opf}");
+        }
+
+        let page = read(&mut zip, "OEBPS/pages/2.xhtml");
+
+        // probed dimensions take precedence over metadata
+        assert!(page.contains(r#"content="width=1, height=1""#), "{page}");
+        assert!(page.contains(r#"src="../images/2.png""#), "{page}");
+
+        let nav = read(&mut zip, "OEBPS/nav.xhtml");
+
+        assert!(
+            nav.contains(r#"<li><a href="pages/2.xhtml">2</a></li>"#),
+            "{nav}"
+        );
+    }
+
+    #[test]
+    fn reject_avif() {
+        let dir = temp_dir("reject_avif_epub");
+
+        let path = dir.join("1.avif");
+        fs::write(&path, AVIF).unwrap();
+
+        let res = write_pages(
+            Cursor::new(Vec::new()),
+            &gallery(),
+            &[(1, path)],
+            Options::default(),
+        );
+
+        fs::remove_dir_all(&dir).ok();
+
+        assert!(matches!(
+            res,
+            Err(crate::Error::Export(Error::UnsupportedFormat(pages)))
+                if pages == [(1, Some(ImageFormat::Avif))]
+        ));
+    }
+}
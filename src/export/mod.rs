@@ -0,0 +1,224 @@
+//! Packages downloaded galleries for readers
+
+use std::{
+    fs,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{Datelike, Timelike};
+use zip::{write::SimpleFileOptions, CompressionMethod};
+
+use crate::{
+    download::Manifest,
+    image::{self, ImageFormat},
+    model::Gallery,
+};
+
+pub mod cbz;
+mod comic_info;
+pub mod epub;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+mod sidecar;
+
+pub use comic_info::*;
+pub use sidecar::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("manifest is not found in {0:?}")]
+    ManifestNotFound(PathBuf),
+
+    #[error("pages are not downloaded: {0:?}")]
+    Incomplete(Vec<usize>),
+
+    #[error("gallery has no page")]
+    Empty,
+
+    /// Download pages as webp or original instead
+    #[error("pages can't be exported in their formats: {0:?}")]
+    UnsupportedFormat(Vec<(usize, Option<ImageFormat>)>),
+
+    #[error("zip: {0}")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+/// Returns gallery and (page, path) of pages downloaded into `dir`
+///
+/// ## Errors
+///
+/// - [`Error::ManifestNotFound`] if `dir` has no manifest
+/// - [`Error::Incomplete`] if any page isn't downloaded yet
+pub(crate) async fn manifest_pages(dir: &Path) -> crate::Result<(Gallery, Vec<(usize, PathBuf)>)> {
+    let manifest = Manifest::load(dir)
+        .await?
+        .ok_or_else(|| Error::ManifestNotFound(dir.to_owned()))?;
+
+    let remaining = manifest.remaining().collect::<Vec<_>>();
+
+    if !remaining.is_empty() {
+        return Err(Error::Incomplete(remaining).into());
+    }
+
+    let pages = manifest
+        .pages
+        .iter()
+        .filter_map(|(page, entry)| Some((*page, dir.join(entry.file_name.as_ref()?))))
+        .collect();
+
+    Ok((manifest.gallery, pages))
+}
+
+/// Sniffs every page before writing, so a gallery isn't rejected halfway
+///
+/// ## Errors
+///
+/// - [`Error::UnsupportedFormat`] if any page isn't in `supported` formats
+pub(crate) fn check_formats(
+    pages: &[(usize, PathBuf)],
+    supported: &[ImageFormat],
+) -> crate::Result<()> {
+    let mut unsupported = Vec::new();
+
+    for (page, path) in pages {
+        let mut head = Vec::with_capacity(64);
+        fs::File::open(path)?.take(64).read_to_end(&mut head)?;
+
+        let format = image::sniff(&head);
+
+        if !format.is_some_and(|format| supported.contains(&format)) {
+            unsupported.push((*page, format));
+        }
+    }
+
+    if !unsupported.is_empty() {
+        return Err(Error::UnsupportedFormat(unsupported).into());
+    }
+
+    Ok(())
+}
+
+/// Runs `write` on blocking thread into `{out}.{pid}-{n}.part`, then renames it to `out`
+///
+/// The part file is removed if `write` fails, so `out` is never partial.
+/// It's unique per call, so concurrent writers of `out` don't clobber each other's part.
+pub(crate) async fn write_file<F>(out: &Path, write: F) -> crate::Result<()>
+where
+    F: FnOnce(BufWriter<fs::File>) -> crate::Result<BufWriter<fs::File>> + Send + 'static,
+{
+    let out = out.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut part = out.as_os_str().to_owned();
+        part.push(format!(
+            ".{}-{}.part",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let part = PathBuf::from(part);
+
+        let res = fs::File::create(&part)
+            .map_err(crate::Error::from)
+            .and_then(|f| write(BufWriter::new(f)))
+            .and_then(|mut f| Ok(f.flush()?));
+
+        if let Err(err) = res {
+            fs::remove_file(&part).ok();
+            return Err(err);
+        }
+
+        fs::rename(&part, &out)?;
+
+        Ok(())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Stores entries without compression, as images are already compressed
+///
+/// Entries are dated `date_added` of `gallery` to keep archive reproducible.
+pub(crate) fn zip_options(gallery: &Gallery) -> SimpleFileOptions {
+    let date = gallery.date_added;
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    // zip can't represent before 1980, left to default
+    match zip::DateTime::from_date_and_time(
+        date.year().try_into().unwrap_or_default(),
+        date.month() as u8,
+        date.day() as u8,
+        date.hour() as u8,
+        date.minute() as u8,
+        (date.second() as u8).min(58),
+    ) {
+        Ok(modified) => options.last_modified_time(modified),
+        Err(_) => options,
+    }
+}
+
+/// Escapes text and attribute value of XML
+pub(crate) fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::model::{Tag, TagKind, Video};
+
+    use super::*;
+
+    /// Header of AVIF, enough to be sniffed
+    pub const AVIF: &[u8] = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+
+    /// Gallery of two pages shared by tests of exports
+    pub fn gallery() -> Gallery {
+        Gallery {
+            kind: "doujinshi".to_owned(),
+            video: Some(Video {
+                name: "video".to_owned(),
+                file_name: "video.mp4".to_owned(),
+            }),
+            language: Some("korean".to_owned()),
+            tags: tags(&[
+                (TagKind::Artist, "foo bar"),
+                (TagKind::Group, "group"),
+                (TagKind::Series, "original"),
+                (TagKind::Character, "alice"),
+                (TagKind::Female, "baz"),
+                (TagKind::Male, "qux"),
+                (TagKind::Misc, "full color"),
+            ]),
+            date_published: Some("2023-12-31T00:00:00Z".parse().unwrap()),
+            ..crate::tests::gallery(&["abc", "abc"])
+        }
+    }
+
+    pub fn tags(tags: &[(TagKind, &str)]) -> Vec<Tag> {
+        tags.iter()
+            .map(|(kind, name)| Tag {
+                kind: *kind,
+                name: (*name).to_owned(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,414 @@
+//! PDF document, one page per image
+//!
+//! JPEG is embedded as is with `DCTDecode`, PNG, GIF and WebP are transcoded to JPEG.
+//! AVIF and JXL can't be transcoded, see [`image::transcode`],
+//! so galleries which have them are rejected before writing.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use itertools::Itertools;
+
+use crate::{
+    image::{self, jpeg_frame, Image, ImageExt, ImageFormat, ImageKind, JpegFrame, TargetFormat},
+    model::{Gallery, TagKind},
+};
+
+use super::{check_formats, manifest_pages, write_file, Error};
+
+/// Formats which can be embedded or transcoded to JPEG
+const SUPPORTED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::Webp,
+];
+
+/// Object numbers of catalog and page tree, pages follow them
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+
+/// Counts written bytes to build cross-reference table
+struct Counter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Document<W> {
+    out: Counter<W>,
+    /// Offset of each object, indexed by object number - 1
+    offsets: Vec<u64>,
+}
+
+impl<W: Write> Document<W> {
+    fn begin(&mut self, number: usize) -> io::Result<()> {
+        if self.offsets.len() < number {
+            self.offsets.resize(number, 0);
+        }
+
+        self.offsets[number - 1] = self.out.written;
+
+        writeln!(self.out, "{number} 0 obj")
+    }
+
+    fn object(&mut self, number: usize, dictionary: &str) -> io::Result<()> {
+        self.begin(number)?;
+        write!(self.out, "{dictionary}\nendobj\n")
+    }
+
+    fn stream(&mut self, number: usize, dictionary: &str, data: &[u8]) -> io::Result<()> {
+        self.begin(number)?;
+
+        // `dictionary` is closed here to append `Length`
+        write!(self.out, "{dictionary} /Length {} >>\nstream\n", data.len())?;
+        self.out.write_all(data)?;
+        write!(self.out, "\nendstream\nendobj\n")
+    }
+}
+
+/// Writes `pages` of `gallery` into `writer` in the given order
+///
+/// `pages` are (page, path of downloaded image).
+/// `quality` is from 1 to 100 and used to transcode pages which aren't JPEG.
+///
+/// Each page is sized by `width` and `height` of [`File`](crate::model::File) in points,
+/// or by dimensions of image if they are unknown.
+///
+/// ## Errors
+///
+/// - [`Error::UnsupportedFormat`] if any page can't be transcoded, e.g. AVIF
+pub fn write_pages<W: Write>(
+    writer: W,
+    gallery: &Gallery,
+    pages: &[(usize, PathBuf)],
+    quality: u8,
+) -> crate::Result<W> {
+    if pages.is_empty() {
+        return Err(Error::Empty.into());
+    }
+
+    check_formats(pages, &SUPPORTED_FORMATS)?;
+
+    let mut doc = Document {
+        out: Counter {
+            inner: writer,
+            written: 0,
+        },
+        offsets: Vec::new(),
+    };
+
+    // binary comment marks file as binary for transfer tools
+    doc.out.write_all(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n")?;
+
+    doc.object(CATALOG, &format!("<< /Type /Catalog /Pages {PAGES} 0 R >>"))?;
+
+    let mut kids = Vec::with_capacity(pages.len());
+
+    for (i, (page, path)) in pages.iter().enumerate() {
+        let (page_obj, image_obj, contents_obj) = (3 + i * 3, 4 + i * 3, 5 + i * 3);
+
+        // only one page is held in memory
+        let (jpeg, frame) = jpeg(fs::read(path)?.into(), path, quality)?;
+
+        let file = gallery
+            .files
+            .iter()
+            .find(|(p, _)| p == page)
+            .map(|(_, file)| file);
+
+        let (width, height) = match file {
+            Some(file) if file.width > 0 && file.height > 0 => {
+                (file.width as u32, file.height as u32)
+            }
+            _ => (frame.width, frame.height),
+        };
+
+        let color_space = match frame.components {
+            1 => "/DeviceGray",
+            _ => "/DeviceRGB",
+        };
+
+        doc.stream(
+            image_obj,
+            &format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {color_space} /BitsPerComponent 8 /Filter /DCTDecode",
+                frame.width, frame.height,
+            ),
+            &jpeg,
+        )?;
+
+        // image space is unit square, scaled to page
+        let contents = format!("q {width} 0 0 {height} 0 0 cm /Im0 Do Q");
+
+        doc.stream(contents_obj, "<<", contents.as_bytes())?;
+
+        doc.object(
+            page_obj,
+            &format!(
+                "<< /Type /Page /Parent {PAGES} 0 R /MediaBox [0 0 {width} {height}] /Resources << /XObject << /Im0 {image_obj} 0 R >> >> /Contents {contents_obj} 0 R >>"
+            ),
+        )?;
+
+        kids.push(format!("{page_obj} 0 R"));
+
+        tracing::trace!(page, width, height, "add page");
+    }
+
+    doc.object(
+        PAGES,
+        &format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        ),
+    )?;
+
+    let info = 3 + pages.len() * 3;
+
+    doc.object(info, &info_dictionary(gallery))?;
+
+    let xref = doc.out.written;
+
+    write!(doc.out, "xref\n0 {}\n0000000000 65535 f \n", info + 1)?;
+
+    for offset in &doc.offsets {
+        writeln!(doc.out, "{offset:010} 00000 n ")?;
+    }
+
+    write!(
+        doc.out,
+        "trailer\n<< /Size {} /Root {CATALOG} 0 R /Info {info} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        info + 1
+    )?;
+
+    Ok(doc.out.inner)
+}
+
+/// Writes `pages` of `gallery` into `out`, see [`write_pages`]
+///
+/// Written to a part file next to `out` and renamed, so `out` is never partial.
+pub async fn write(
+    gallery: &Gallery,
+    pages: Vec<(usize, PathBuf)>,
+    out: impl AsRef<Path>,
+    quality: u8,
+) -> crate::Result<()> {
+    let gallery = gallery.clone();
+
+    write_file(out.as_ref(), move |f| {
+        write_pages(f, &gallery, &pages, quality)
+    })
+    .await
+}
+
+/// Writes pages downloaded into `dir` by [`GalleryDownloader`](crate::download::GalleryDownloader)
+///
+/// See [`manifest_pages`](super::manifest_pages) and [`write_pages`] for errors,
+/// AVIF is downloaded by default, so download as webp or original for PDF.
+pub async fn from_manifest(
+    dir: impl AsRef<Path>,
+    out: impl AsRef<Path>,
+    quality: u8,
+) -> crate::Result<()> {
+    let (gallery, pages) = manifest_pages(dir.as_ref()).await?;
+
+    write(&gallery, pages, out, quality).await
+}
+
+/// Returns `buf` if `DCTDecode` can read it, otherwise transcodes it to JPEG
+fn jpeg(buf: Bytes, path: &Path, quality: u8) -> crate::Result<(Bytes, JpegFrame)> {
+    let format = image::sniff(&buf);
+
+    // baseline or progressive, 8 bits, grayscale or YCbCr
+    let embeddable = match format {
+        Some(ImageFormat::Jpeg) => jpeg_frame(&buf).filter(|frame| {
+            matches!(frame.marker, 0xc0..=0xc2)
+                && frame.precision == 8
+                && matches!(frame.components, 1 | 3)
+        }),
+        _ => None,
+    };
+
+    if let Some(frame) = embeddable {
+        return Ok((buf, frame));
+    }
+
+    let ext = match format {
+        Some(ImageFormat::Avif) => ImageExt::Avif,
+        Some(ImageFormat::Webp) => ImageExt::Webp,
+        Some(ImageFormat::Jxl) => ImageExt::Jxl,
+        _ => ImageExt::Original,
+    };
+
+    tracing::debug!(?path, ?format, "transcode page to JPEG");
+
+    let image = Image {
+        kind: ImageKind::Original,
+        ext,
+        url: path.to_string_lossy().into_owned(),
+        buf,
+    };
+
+    let buf = image.transcode(TargetFormat::Jpeg, quality)?.buf;
+
+    let frame = jpeg_frame(&buf).ok_or(image::Error::UnsupportedTranscode(ext))?;
+
+    Ok((buf, frame))
+}
+
+fn info_dictionary(gallery: &Gallery) -> String {
+    let date = gallery.date_published.unwrap_or(gallery.date_added);
+    let date = date.format("D:%Y%m%d%H%M%SZ").to_string();
+
+    let entries = [
+        ("Title", gallery.title.clone()),
+        ("Author", gallery.tag_names(TagKind::Artist).join(", ")),
+        ("Subject", gallery.url()),
+        (
+            "Keywords",
+            gallery
+                .tags
+                .iter()
+                .map(|tag| tag.name.as_str())
+                .unique()
+                .join(", "),
+        ),
+        ("Creator", env!("CARGO_PKG_NAME").to_owned()),
+        ("CreationDate", date),
+    ];
+
+    let entries = entries
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("/{key} {}", text_string(value)))
+        .join(" ");
+
+    format!("<< {entries} >>")
+}
+
+/// Encodes `s` as PDF text string, non-ASCII is UTF-16BE with BOM
+fn text_string(s: &str) -> String {
+    if s.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        let escaped = s
+            .replace('\\', "\\\\")
+            .replace('(', "\\(")
+            .replace(')', "\\)");
+
+        format!("({escaped})")
+    } else {
+        let hex = s.encode_utf16().map(|unit| format!("{unit:04X}")).join("");
+
+        format!("<FEFF{hex}>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::image::{codecs::webp::WebPEncoder, ImageEncoder, RgbaImage};
+
+    use crate::{
+        export::tests::{gallery, tags, AVIF},
+        tests::temp_dir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn write_pdf() {
+        let dir = temp_dir("write_pdf");
+
+        let mut webp = Vec::new();
+
+        WebPEncoder::new_lossless(&mut webp)
+            .write_image(
+                RgbaImage::from_pixel(4, 3, [255, 0, 0, 255].into()).as_raw(),
+                4,
+                3,
+                ::image::ExtendedColorType::Rgba8,
+            )
+            .unwrap();
+
+        let path = dir.join("1.webp");
+        fs::write(&path, webp).unwrap();
+
+        let gallery = Gallery {
+            title: "제목 (1)".to_owned(),
+            tags: tags(&[(TagKind::Artist, "foo (bar)")]),
+            ..gallery()
+        };
+
+        let buf = write_pages(Vec::new(), &gallery, &[(1, path)], 90).unwrap();
+
+        fs::remove_dir_all(&dir).ok();
+
+        let pdf = String::from_utf8_lossy(&buf);
+
+        assert!(buf.starts_with(b"%PDF-1.7\n"));
+        assert!(buf.ends_with(b"%%EOF\n"));
+
+        for s in [
+            "/MediaBox [0 0 800 1200]",
+            "/Width 4 /Height 3 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode",
+            "/Author (foo \\(bar\\))",
+            "/Title <FEFFC81CBAA90020002800310029>",
+            "/CreationDate (D:20231231000000Z)",
+            "/Count 1",
+        ] {
+            assert!(pdf.contains(s), "{s}");
+        }
+
+        // every offset of cross-reference table points an object
+        let xref = buf.windows(6).rposition(|w| w == b"\nxref\n").unwrap() + 1;
+
+        let table = String::from_utf8(buf[xref..].to_vec()).unwrap();
+
+        for (i, line) in table.lines().skip(3).take(6).enumerate() {
+            let offset = line[..10].parse::<usize>().unwrap();
+
+            assert!(
+                buf[offset..].starts_with(format!("{} 0 obj", i + 1).as_bytes()),
+                "{line}"
+            );
+        }
+
+        assert!(table.contains(&format!("startxref\n{xref}\n")));
+    }
+
+    #[test]
+    fn reject_avif() {
+        let dir = temp_dir("reject_avif_pdf");
+
+        let jpeg = dir.join("1.jpg");
+        fs::write(&jpeg, [0xff, 0xd8, 0xff, 0xe0]).unwrap();
+
+        let avif = dir.join("2.avif");
+        fs::write(&avif, AVIF).unwrap();
+
+        // rejected before the first page is written
+        let res = write_pages(Vec::new(), &gallery(), &[(1, jpeg), (2, avif)], 90);
+
+        fs::remove_dir_all(&dir).ok();
+
+        assert!(matches!(
+            res,
+            Err(crate::Error::Export(Error::UnsupportedFormat(pages)))
+                if pages == [(2, Some(ImageFormat::Avif))]
+        ));
+    }
+}
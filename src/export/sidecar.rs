@@ -0,0 +1,434 @@
+use std::{fmt::Write, io, path::Path};
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::model::{Gallery, TagKind};
+
+use super::{comic_info, escape_xml, write_file};
+
+/// Version of [`Sidecar::Json`] schema, bumped on breaking change
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Metadata file written next to downloaded pages for other archive tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sidecar {
+    /// `hitomi.json`, shaped as `galleries/{id}.js` of hitomi
+    ///
+    /// Rebuilt from [`Gallery`], so fields which it doesn't keep are omitted.
+    Raw,
+    /// `gallery.json`, stable schema of this crate versioned by [`SCHEMA_VERSION`]
+    Json,
+    /// `info.json`, as gallery-dl writes with `--write-metadata`
+    GalleryDl,
+    /// `ComicInfo.xml`, see [`comic_info`]
+    ComicInfo,
+    /// `movie.nfo`, as Kodi and Jellyfin read for a folder, e.g. of anime gallery
+    Nfo,
+}
+
+impl Sidecar {
+    pub fn all() -> [Self; 5] {
+        [
+            Sidecar::Raw,
+            Sidecar::Json,
+            Sidecar::GalleryDl,
+            Sidecar::ComicInfo,
+            Sidecar::Nfo,
+        ]
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Sidecar::Raw => "hitomi.json",
+            Sidecar::Json => "gallery.json",
+            Sidecar::GalleryDl => "info.json",
+            Sidecar::ComicInfo => "ComicInfo.xml",
+            Sidecar::Nfo => "movie.nfo",
+        }
+    }
+
+    /// Renders metadata of `gallery`
+    pub fn render(&self, gallery: &Gallery) -> String {
+        match self {
+            Sidecar::Raw => pretty(&raw(gallery)),
+            Sidecar::Json => pretty(&Schema::from(gallery)),
+            Sidecar::GalleryDl => pretty(&gallery_dl(gallery)),
+            Sidecar::ComicInfo => {
+                let pages = gallery.files.iter().map(|(page, _)| *page).collect_vec();
+
+                comic_info(gallery, &pages)
+            }
+            Sidecar::Nfo => nfo(gallery),
+        }
+    }
+
+    /// Writes metadata of `gallery` into `dir`, replacing existing one atomically
+    pub async fn write(&self, gallery: &Gallery, dir: impl AsRef<Path>) -> crate::Result<()> {
+        let rendered = self.render(gallery);
+
+        write_file(&dir.as_ref().join(self.file_name()), move |mut f| {
+            io::Write::write_all(&mut f, rendered.as_bytes())?;
+            Ok(f)
+        })
+        .await
+    }
+}
+
+fn pretty(value: &impl Serialize) -> String {
+    // serializing maps with string keys never fails
+    serde_json::to_string_pretty(value).unwrap()
+}
+
+#[derive(Serialize)]
+struct Schema<'a> {
+    schema: u32,
+    id: u32,
+    title: &'a str,
+    kind: &'a str,
+    language: Option<&'a str>,
+    language_code: Option<&'static str>,
+    url: String,
+    date_added: DateTime<Utc>,
+    date_published: Option<DateTime<Utc>>,
+    artists: Vec<&'a str>,
+    groups: Vec<&'a str>,
+    series: Vec<&'a str>,
+    characters: Vec<&'a str>,
+    /// female, male and misc tags
+    tags: Vec<SchemaTag<'a>>,
+    pages: Vec<SchemaPage<'a>>,
+    video: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct SchemaTag<'a> {
+    kind: TagKind,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct SchemaPage<'a> {
+    page: usize,
+    hash: &'a str,
+    name: &'a str,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> From<&'a Gallery> for Schema<'a> {
+    fn from(gallery: &'a Gallery) -> Self {
+        let names = |kind| gallery.tag_names(kind).collect();
+
+        Schema {
+            schema: SCHEMA_VERSION,
+            id: gallery.id,
+            title: &gallery.title,
+            kind: &gallery.kind,
+            language: gallery.language.as_deref(),
+            language_code: gallery.language_code(),
+            url: gallery.url(),
+            date_added: gallery.date_added,
+            date_published: gallery.date_published,
+            artists: names(TagKind::Artist),
+            groups: names(TagKind::Group),
+            series: names(TagKind::Series),
+            characters: names(TagKind::Character),
+            tags: gallery
+                .tags
+                .iter()
+                .filter(|tag| matches!(tag.kind, TagKind::Female | TagKind::Male | TagKind::Misc))
+                .map(|tag| SchemaTag {
+                    kind: tag.kind,
+                    name: &tag.name,
+                })
+                .collect(),
+            pages: gallery
+                .files
+                .iter()
+                .map(|(page, file)| SchemaPage {
+                    page: *page,
+                    hash: &file.hash,
+                    name: &file.name,
+                    width: file.width,
+                    height: file.height,
+                })
+                .collect(),
+            video: gallery.video.as_ref().map(|video| video.file_name.as_str()),
+        }
+    }
+}
+
+/// Path of tag page on hitomi, e.g. `/artist/foo%20bar-all.html`
+fn tag_url(kind: &str, name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+
+    for b in name.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+
+    format!("/{kind}/{encoded}-all.html")
+}
+
+fn raw(gallery: &Gallery) -> Value {
+    let flag = |x: bool| u8::from(x);
+
+    let date = |date: DateTime<Utc>| date.format("%Y-%m-%d %H:%M:%S+00").to_string();
+
+    let names = |kind: TagKind, key: &str, path: &str| {
+        gallery
+            .tag_names(kind)
+            .map(|name| json!({ key: name, "url": tag_url(path, name) }))
+            .collect_vec()
+    };
+
+    let tags = gallery
+        .tags
+        .iter()
+        .filter_map(|tag| {
+            let (female, male, url) = match tag.kind {
+                TagKind::Female => ("1", "", tag_url("tag", &format!("female:{}", tag.name))),
+                TagKind::Male => ("", "1", tag_url("tag", &format!("male:{}", tag.name))),
+                TagKind::Misc => ("", "", tag_url("tag", &tag.name)),
+                _ => return None,
+            };
+
+            Some(json!({ "tag": tag.name, "female": female, "male": male, "url": url }))
+        })
+        .collect_vec();
+
+    let files = gallery
+        .files
+        .iter()
+        .map(|(_, file)| {
+            json!({
+                "hash": file.hash,
+                "name": file.name,
+                "width": file.width,
+                "height": file.height,
+                "hasavif": flag(file.has_avif),
+                "haswebp": flag(file.has_webp),
+                "hasjxl": flag(file.has_jxl),
+            })
+        })
+        .collect_vec();
+
+    json!({
+        "id": gallery.id.to_string(),
+        "title": gallery.title,
+        "type": gallery.kind,
+        "language": gallery.language,
+        "galleryurl": format!("/galleries/{}.html", gallery.id),
+        "date": date(gallery.date_added),
+        "datepublished": gallery.date_published.map(date),
+        "artists": names(TagKind::Artist, "artist", "artist"),
+        "groups": names(TagKind::Group, "group", "group"),
+        "parodys": names(TagKind::Series, "parody", "series"),
+        "characters": names(TagKind::Character, "character", "character"),
+        "tags": tags,
+        "files": files,
+        "video": gallery.video.as_ref().map(|video| &video.name),
+        "videofilename": gallery.video.as_ref().map(|video| &video.file_name),
+    })
+}
+
+fn gallery_dl(gallery: &Gallery) -> Value {
+    let names = |kind| gallery.tag_names(kind).collect_vec();
+
+    // gallery-dl marks gender of tags with a symbol by default
+    let tags = gallery
+        .tags
+        .iter()
+        .filter_map(|tag| match tag.kind {
+            TagKind::Female => Some(format!("{} ♀", tag.name)),
+            TagKind::Male => Some(format!("{} ♂", tag.name)),
+            TagKind::Misc => Some(tag.name.clone()),
+            _ => None,
+        })
+        .collect_vec();
+
+    let capitalize = |s: &str| {
+        let mut chars = s.chars();
+
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+
+    json!({
+        "category": "hitomi",
+        "subcategory": "gallery",
+        "gallery_id": gallery.id,
+        "title": gallery.title,
+        "type": capitalize(&gallery.kind),
+        "language": gallery.language.as_deref().map(capitalize),
+        "lang": gallery.language_code(),
+        "date": gallery.date_added.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "tags": tags,
+        "artist": names(TagKind::Artist),
+        "group": names(TagKind::Group),
+        "parody": names(TagKind::Series),
+        "characters": names(TagKind::Character),
+        "count": gallery.files.len(),
+    })
+}
+
+fn nfo(gallery: &Gallery) -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>"#,
+        "\n<movie>\n",
+    ));
+
+    let mut element = |name: &str, value: &str| {
+        writeln!(xml, "  <{name}>{}</{name}>", escape_xml(value)).unwrap();
+    };
+
+    let date = gallery.date_published.unwrap_or(gallery.date_added);
+
+    element("title", &gallery.title);
+    element("premiered", &date.format("%Y-%m-%d").to_string());
+    element("year", &date.format("%Y").to_string());
+    element(
+        "dateadded",
+        &gallery.date_added.format("%Y-%m-%d %H:%M:%S").to_string(),
+    );
+    element("genre", &gallery.kind);
+
+    for artist in gallery.tag_names(TagKind::Artist) {
+        element("credits", artist);
+    }
+
+    for group in gallery.tag_names(TagKind::Group) {
+        element("studio", group);
+    }
+
+    for series in gallery.tag_names(TagKind::Series) {
+        element("tag", &format!("series:{series}"));
+    }
+
+    for tag in gallery
+        .tags
+        .iter()
+        .filter(|tag| matches!(tag.kind, TagKind::Female | TagKind::Male | TagKind::Misc))
+    {
+        element("tag", &tag.name);
+    }
+
+    if let Some(language) = &gallery.language {
+        element("tag", &format!("language:{language}"));
+    }
+
+    for character in gallery.tag_names(TagKind::Character) {
+        writeln!(
+            xml,
+            "  <actor>\n    <role>{}</role>\n  </actor>",
+            escape_xml(character)
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        xml,
+        r#"  <uniqueid type="hitomi" default="true">{}</uniqueid>"#,
+        gallery.id
+    )
+    .unwrap();
+    writeln!(xml, "  <website>{}</website>", escape_xml(&gallery.url())).unwrap();
+
+    xml.push_str("</movie>\n");
+
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::gallery;
+
+    use super::*;
+
+    #[test]
+    fn render_raw() {
+        let gallery = gallery();
+
+        let json = Sidecar::Raw.render(&gallery);
+
+        assert!(
+            json.contains(r#""url": "/artist/foo%20bar-all.html""#),
+            "{json}"
+        );
+        assert!(
+            json.contains(r#""url": "/tag/female%3Abaz-all.html""#),
+            "{json}"
+        );
+
+        // hitomi-shaped json is parsed back into the same gallery
+        let parsed = crate::gallery::from_json(&json).unwrap();
+
+        assert_eq!(parsed.id, gallery.id);
+        assert_eq!(parsed.files, gallery.files);
+        assert_eq!(parsed.tags, gallery.tags);
+        assert_eq!(parsed.video, gallery.video);
+        assert_eq!(parsed.date_added, gallery.date_added);
+        assert_eq!(parsed.date_published, gallery.date_published);
+    }
+
+    #[test]
+    fn render_json() {
+        let json = Sidecar::Json.render(&gallery());
+        let value = serde_json::from_str::<Value>(&json).unwrap();
+
+        assert_eq!(value["schema"], SCHEMA_VERSION);
+        assert_eq!(value["language_code"], "ko");
+        assert_eq!(value["url"], "https://hitomi.la/galleries/123.html");
+        assert_eq!(value["date_added"], "2024-01-02T03:04:05Z");
+        assert_eq!(value["artists"], json!(["foo bar"]));
+        assert_eq!(value["tags"][0], json!({ "kind": "female", "name": "baz" }));
+        assert_eq!(value["pages"][1]["page"], 2);
+        assert_eq!(value["video"], "video.mp4");
+    }
+
+    #[test]
+    fn render_gallery_dl() {
+        let json = Sidecar::GalleryDl.render(&gallery());
+        let value = serde_json::from_str::<Value>(&json).unwrap();
+
+        assert_eq!(value["gallery_id"], 123);
+        assert_eq!(value["type"], "Doujinshi");
+        assert_eq!(value["language"], "Korean");
+        assert_eq!(value["lang"], "ko");
+        assert_eq!(value["date"], "2024-01-02 03:04:05");
+        assert_eq!(value["tags"], json!(["baz ♀", "qux ♂", "full color"]));
+        assert_eq!(value["parody"], json!(["original"]));
+        assert_eq!(value["count"], 2);
+    }
+
+    #[test]
+    fn render_nfo() {
+        let xml = Sidecar::Nfo.render(&gallery());
+
+        for element in [
+            "<title>title</title>",
+            "<premiered>2023-12-31</premiered>",
+            "<dateadded>2024-01-02 03:04:05</dateadded>",
+            "<credits>foo bar</credits>",
+            "<studio>group</studio>",
+            "<tag>series:original</tag>",
+            "<tag>full color</tag>",
+            "<tag>language:korean</tag>",
+            "<role>alice</role>",
+            r#"<uniqueid type="hitomi" default="true">123</uniqueid>"#,
+        ] {
+            assert!(xml.contains(element), "{element}\n{xml}");
+        }
+
+        assert!(xml.ends_with("</movie>\n"));
+    }
+}
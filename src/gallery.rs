@@ -1,3 +1,6 @@
+use chrono::{DateTime, Utc};
+use futures::{future::Either, stream, Stream, StreamExt};
+use regex::Regex;
 use reqwest::{Method, StatusCode};
 use tap::Tap;
 
@@ -5,7 +8,7 @@ use crate::{
     model,
     network::{
         self,
-        http::{BASE_DOMAIN, request},
+        http::{request, BASE_DOMAIN},
     },
 };
 
@@ -30,6 +33,8 @@ mod sealed {
         pub hasavif: Flag,
         #[serde(with = "either::serde_untagged", default = "default_flag")]
         pub haswebp: Flag,
+        #[serde(with = "either::serde_untagged", default = "default_flag")]
+        pub hasjxl: Flag,
         pub height: usize,
         pub width: usize,
         pub name: String,
@@ -101,16 +106,24 @@ mod sealed {
         #[serde(rename = "parodys", default, deserialize_with = "unwrap_or_default")]
         pub series: Vec<Series>,
         pub date: String,
+        #[serde(default)]
+        pub datepublished: Option<String>,
+        #[serde(default)]
+        pub video: Option<String>,
+        #[serde(default)]
+        pub videofilename: Option<String>,
     }
 
     impl From<File> for model::File {
         fn from(file: File) -> Self {
             let has_webp = file.haswebp.right_or_else(|x| x.parse().unwrap_or(0)) == 1_u8;
             let has_avif = file.hasavif.right_or_else(|x| x.parse().unwrap_or(0)) == 1_u8;
+            let has_jxl = file.hasjxl.right_or_else(|x| x.parse().unwrap_or(0)) == 1_u8;
 
             Self {
                 has_webp,
                 has_avif,
+                has_jxl,
                 width: file.width,
                 height: file.height,
                 hash: file.hash,
@@ -184,10 +197,27 @@ mod sealed {
             let characters = g.characters.into_iter().map_into();
             let tags = g.tags.into_iter().map_into();
 
-            let date = {
-                let x = g.date.trim().replacen(' ', "T", 1) + ":00";
-                x.parse().map_err(|e| Error::ParseDateTime(x, e))
-            }?;
+            let date_added = super::parse_date(&g.date)?;
+            let date_published = g
+                .datepublished
+                .as_deref()
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(super::parse_date)
+                .transpose()?;
+
+            let video = match (g.video, g.videofilename) {
+                (Some(name), Some(file_name)) => Some(model::Video { name, file_name }),
+                (Some(name), None) => Some(model::Video {
+                    file_name: name.clone(),
+                    name,
+                }),
+                (None, Some(file_name)) => Some(model::Video {
+                    name: file_name.clone(),
+                    file_name,
+                }),
+                (None, None) => None,
+            };
 
             Ok(Self {
                 id,
@@ -199,6 +229,7 @@ mod sealed {
                     .enumerate()
                     .map(|(i, file)| (i + 1, file.into()))
                     .collect(),
+                video,
                 language: g.language,
                 tags: artists
                     .chain(groups)
@@ -206,7 +237,8 @@ mod sealed {
                     .chain(characters)
                     .chain(tags)
                     .collect(),
-                date,
+                date_added,
+                date_published,
             })
         }
     }
@@ -223,8 +255,23 @@ pub enum Error {
     )]
     DeserializeGallery(String, serde_json::Error),
 
-    #[error("parse datetime: {0}: {1}")]
-    ParseDateTime(String, chrono::ParseError),
+    #[error("parse datetime: {0}")]
+    ParseDateTime(String),
+
+    #[error("parse gallery block: missing {1}: id = {0}")]
+    ParseGalleryBlock(u32, &'static str),
+}
+
+fn parse_date(s: &str) -> Result<DateTime<Utc>, Error> {
+    crate::date::parse(s).ok_or_else(|| Error::ParseDateTime(s.to_owned()))
+}
+
+/// Parses gallery JSON as hitomi serves, which follows `var galleryinfo = `
+pub(crate) fn from_json(json: &str) -> Result<model::Gallery, Error> {
+    serde_json::from_str::<sealed::Gallery>(json)
+        .map_err(|err| Error::DeserializeGallery(json.to_owned(), err))?
+        .tap(|x| tracing::debug!("{x:?}"))
+        .try_into()
 }
 
 /// Fetches gallery js from hitomi server and Returns gallery information
@@ -250,10 +297,7 @@ pub async fn parse(id: u32) -> crate::Result<Option<model::Gallery>> {
 
     let (_, x) = txt.split_once('=').unwrap_or_default();
 
-    let gallery: model::Gallery = serde_json::from_str::<sealed::Gallery>(x)
-        .map_err(|err| Error::DeserializeGallery(txt, err))?
-        .tap(|x| tracing::debug!("{x:?}"))
-        .try_into()?;
+    let gallery = from_json(x)?;
 
     tracing::debug!("{gallery:?}");
     tracing::debug!("page={}", gallery.files.len());
@@ -261,6 +305,238 @@ pub async fn parse(id: u32) -> crate::Result<Option<model::Gallery>> {
     Ok(Some(gallery))
 }
 
+/// Fetches galleries concurrently and Returns `(id, result)` in the same order as `ids`
+///
+/// Failure of a gallery doesn't abort the others.
+/// `concurrency` of zero is treated as one.
+pub fn parse_many(
+    ids: impl IntoIterator<Item = u32>,
+    concurrency: usize,
+) -> impl Stream<Item = (u32, crate::Result<Option<model::Gallery>>)> {
+    parse_many_with(ids, concurrency, true)
+}
+
+/// Same as [`parse_many`], but Returns `(id, result)` as soon as each gallery is fetched
+pub fn parse_many_unordered(
+    ids: impl IntoIterator<Item = u32>,
+    concurrency: usize,
+) -> impl Stream<Item = (u32, crate::Result<Option<model::Gallery>>)> {
+    parse_many_with(ids, concurrency, false)
+}
+
+fn parse_many_with(
+    ids: impl IntoIterator<Item = u32>,
+    concurrency: usize,
+    ordered: bool,
+) -> impl Stream<Item = (u32, crate::Result<Option<model::Gallery>>)> {
+    let concurrency = concurrency.max(1);
+
+    let futs = stream::iter(ids).map(|id| async move { (id, parse(id).await) });
+
+    if ordered {
+        Either::Left(futs.buffered(concurrency))
+    } else {
+        Either::Right(futs.buffer_unordered(concurrency))
+    }
+}
+
+/// Fetches gallery block html from hitomi server and Returns summary of gallery
+///
+/// Gallery block is much smaller than gallery js, because it doesn't contain files.
+///
+/// ## Return
+///
+/// Returns `None` if status code is NOT_FOUND
+pub async fn block(id: u32) -> crate::Result<Option<model::GalleryBlock>> {
+    let url = format!("https://ltn.{}/galleryblock/{}.html", BASE_DOMAIN, id);
+
+    let resp = request(Method::GET, &url).await?;
+    let status_code = resp.status();
+
+    if !status_code.is_success() {
+        if status_code == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        return Err(network::http::Error::Status(status_code).into());
+    }
+
+    let html = resp.text().await?;
+
+    let block = parse_block(id, &html)?;
+
+    tracing::debug!("{block:?}");
+
+    Ok(Some(block))
+}
+
+fn parse_block(id: u32, html: &str) -> Result<model::GalleryBlock, Error> {
+    let title_regex = Regex::new(r#"(?si)<h1[^>]*>\s*<a[^>]*>(?<title>.*?)</a>"#).unwrap();
+
+    let title = title_regex
+        .captures(html)
+        .map(|caps| unescape_html(caps["title"].trim()))
+        .ok_or(Error::ParseGalleryBlock(id, "title"))?;
+
+    //
+
+    let kind_regex = Regex::new(r#"(?si)href="/type/(?<kind>[^"]+?)-all\.html""#).unwrap();
+
+    let kind = kind_regex
+        .captures(html)
+        .map(|caps| caps["kind"].to_owned())
+        .ok_or(Error::ParseGalleryBlock(id, "type"))?;
+
+    //
+
+    let language_regex = Regex::new(r#"(?si)href="/index-(?<language>[^"]+?)\.html""#).unwrap();
+
+    let language = language_regex
+        .captures(html)
+        .map(|caps| caps["language"].to_owned())
+        .filter(|language| language != "all");
+
+    //
+
+    let tag_regex = Regex::new(
+        r#"(?si)<a[^>]*href="/(?<kind>artist|group|series|character|tag)/(?<href>[^"]*)"[^>]*>(?<name>.*?)</a>"#,
+    )
+    .unwrap();
+
+    let (mut artists, mut series) = (Vec::new(), Vec::new());
+
+    let tags = tag_regex
+        .captures_iter(html)
+        .map(|caps| {
+            let name = unescape_html(caps["name"].trim());
+
+            let kind = match &caps["kind"] {
+                "artist" => model::TagKind::Artist,
+                "group" => model::TagKind::Group,
+                "series" => model::TagKind::Series,
+                "character" => model::TagKind::Character,
+                _ if caps["href"].starts_with("female") => model::TagKind::Female,
+                _ if caps["href"].starts_with("male") => model::TagKind::Male,
+                _ => model::TagKind::Misc,
+            };
+
+            let name = match kind {
+                model::TagKind::Female => name.trim_end_matches('♀').trim_end().to_owned(),
+                model::TagKind::Male => name.trim_end_matches('♂').trim_end().to_owned(),
+                _ => name,
+            };
+
+            model::Tag { kind, name }
+        })
+        .filter_map(|tag| match tag.kind {
+            model::TagKind::Artist => {
+                artists.push(tag.name);
+                None
+            }
+            model::TagKind::Series => {
+                series.push(tag.name);
+                None
+            }
+            _ => Some(tag),
+        })
+        .collect();
+
+    //
+
+    let date_regex = Regex::new(r#"(?si)<p\s+class="date"[^>]*>(?<date>[^<]+)</p>"#).unwrap();
+
+    let date = date_regex
+        .captures(html)
+        .map(|caps| parse_date(&caps["date"]))
+        .ok_or(Error::ParseGalleryBlock(id, "date"))??;
+
+    //
+
+    let thumbnail_regex = Regex::new(r#"(?si)data-src(?:set)?="(?<src>[^"]+)""#).unwrap();
+
+    let mut thumbnails = Vec::new();
+
+    for caps in thumbnail_regex.captures_iter(html) {
+        // srcset: "url 1x, url 2x"
+        for src in caps["src"].split(',') {
+            let Some(src) = src.split_whitespace().next() else {
+                continue;
+            };
+
+            let src = match src.strip_prefix("//") {
+                Some(x) => format!("https://{x}"),
+                None => src.to_owned(),
+            };
+
+            if !thumbnails.contains(&src) {
+                thumbnails.push(src);
+            }
+        }
+    }
+
+    Ok(model::GalleryBlock {
+        id,
+        title,
+        kind,
+        language,
+        artists,
+        series,
+        tags,
+        date,
+        thumbnails,
+    })
+}
+
+fn unescape_html(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| (&rest[1..end], end));
+
+        let decoded = entity.and_then(|(name, end)| {
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let code = match name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => name.strip_prefix('#').and_then(|x| x.parse().ok()),
+                    };
+
+                    char::from_u32(code?)?
+                }
+            };
+
+            Some((c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                res.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    res.push_str(rest);
+
+    res
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -298,4 +574,53 @@ mod tests {
         tracing::debug!("id={}", g.id);
         tracing::debug!("kind={}", g.kind);
     }
+
+    #[test]
+    fn parse_gallery_block() {
+        tracing();
+
+        let html = r#"<div class="dj">
+<a href="/doujinshi/title-korean-1234.html"><div class="dj-img-cont"><div class="dj-img1"><picture><source type="image/avif" data-srcset="//tn.gold-usergeneratedcontent.net/avifsmallbigtn/3/f2/abc.avif 1x, //tn.gold-usergeneratedcontent.net/avifbigtn/3/f2/abc.avif 2x"><img data-src="//tn.gold-usergeneratedcontent.net/webpsmallbigtn/3/f2/abc.webp"></picture></div></div></a>
+<h1 class="lillie"><a href="/doujinshi/title-korean-1234.html">Tom &amp; Jerry&#39;s</a></h1>
+<div class="artist-list"><ul><li><a href="/artist/foo-all.html">foo</a></li></ul></div>
+<div class="dj-content">
+<table class="dj-desc">
+<tr><td>Series</td><td><ul><li><a href="/series/original-all.html">original</a></li></ul></td></tr>
+<tr><td>Type</td><td><a href="/type/doujinshi-all.html">doujinshi</a></td></tr>
+<tr><td>Language</td><td><a href="/index-korean.html">한국어</a></td></tr>
+<tr><td>Tags</td><td class="relatedtags"><ul class="tags"><li><a href="/tag/female%3Abig%20breasts-all.html">big breasts ♀</a></li><li><a href="/tag/male%3Aglasses-all.html">glasses ♂</a></li><li><a href="/tag/full%20color-all.html">full color</a></li></ul></td></tr>
+</table>
+<p class="date">2023-06-13 03:22:00-05</p>
+</div>
+</div>"#;
+
+        let block = parse_block(1234, html).unwrap();
+
+        assert_eq!(block.title, "Tom & Jerry's");
+        assert_eq!(block.kind, "doujinshi");
+        assert_eq!(block.language.as_deref(), Some("korean"));
+        assert_eq!(block.artists, ["foo"]);
+        assert_eq!(block.series, ["original"]);
+        assert_eq!(
+            block.tags,
+            [
+                (model::TagKind::Female, "big breasts"),
+                (model::TagKind::Male, "glasses"),
+                (model::TagKind::Misc, "full color"),
+            ]
+            .map(|(kind, name)| model::Tag {
+                kind,
+                name: name.to_owned()
+            })
+        );
+        assert_eq!(block.date.to_rfc3339(), "2023-06-13T08:22:00+00:00");
+        assert_eq!(
+            block.thumbnails,
+            [
+                "https://tn.gold-usergeneratedcontent.net/avifsmallbigtn/3/f2/abc.avif",
+                "https://tn.gold-usergeneratedcontent.net/avifbigtn/3/f2/abc.avif",
+                "https://tn.gold-usergeneratedcontent.net/webpsmallbigtn/3/f2/abc.webp",
+            ]
+        );
+    }
 }
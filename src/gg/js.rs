@@ -0,0 +1,819 @@
+//! Interpreter for the subset of JavaScript used by gg.js
+//!
+//! Supports `var`/`let`/`const`, `switch`/`case`/`default`, `if`/`else`,
+//! assignment and integer arithmetic, which is enough to evaluate `m(g)`
+//! without depending on the shape of obfuscation.
+
+use std::collections::HashMap;
+
+use super::GG;
+
+/// `g` is 12 bits parsed from the last 3 hex digits of image hash
+const G_RANGE: std::ops::Range<u32> = 0..0x1000;
+
+pub(super) fn parse(s: &str) -> Option<GG> {
+    let tokens = tokenize(s)?;
+
+    let m = parse_function(&tokens)?;
+
+    let mut values = Vec::with_capacity(G_RANGE.len());
+
+    for g in G_RANGE {
+        let value = m.call(g.into())?;
+        values.push(u32::try_from(value).ok()?);
+    }
+
+    // most frequent value is `default`, the others are kept in table
+    let mut counts = HashMap::<u32, usize>::new();
+
+    for value in &values {
+        *counts.entry(*value).or_default() += 1;
+    }
+
+    let default = counts
+        .into_iter()
+        .max_by_key(|(value, count)| (*count, std::cmp::Reverse(*value)))
+        .map(|(value, _)| value)?;
+
+    let m = G_RANGE
+        .zip(values)
+        .filter(|(_, value)| *value != default)
+        .collect();
+
+    let b = parse_b(&tokens)?;
+
+    Some(GG {
+        m,
+        b: b.strip_suffix('/').unwrap_or(&b).to_owned(),
+        default,
+        fetched_at: chrono::Utc::now(),
+    })
+}
+
+/// `b: '1745424004/'`
+fn parse_b(tokens: &[Token]) -> Option<String> {
+    tokens.windows(3).find_map(|xs| match xs {
+        [Token::Ident(name), Token::Punct(":"), Token::Str(b)] if name == "b" => Some(b.clone()),
+        _ => None,
+    })
+}
+
+/// `m: function(g) { ... }`
+fn parse_function(tokens: &[Token]) -> Option<Function> {
+    let start = tokens.windows(4).position(|xs| {
+        matches!(xs, [Token::Ident(m), Token::Punct(":"), Token::Ident(f), Token::Punct("(")] if m == "m" && f == "function")
+    })?;
+
+    let mut parser = Parser {
+        tokens: &tokens[start + 3..],
+        pos: 0,
+    };
+
+    parser.expect("(")?;
+    let arg = parser.ident()?;
+    parser.expect(")")?;
+
+    let body = parser.block()?;
+
+    Some(Function { arg, body })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(i64),
+    Str(String),
+    Punct(&'static str),
+    Regex,
+}
+
+const PUNCTS: &[&str] = &[
+    ">>>=", "===", "!==", ">>>", "<<=", ">>=", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "|=", "^=", "++", "--", "<<", ">>", "=", "+", "-", "*", "/", "%", "<",
+    ">", "!", "~", "&", "|", "^", "(", ")", "{", "}", "[", "]", ";", ":", ",", ".", "?",
+];
+
+fn tokenize(s: &str) -> Option<Vec<Token>> {
+    let bytes = s.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let rest = &s[i..];
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if rest.starts_with("//") {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("/*") {
+            i += rest.find("*/")? + 2;
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let x = &rest[..len];
+
+            let n = match x.strip_prefix("0x").or(x.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+                None => x.parse().ok()?,
+            };
+
+            tokens.push(Token::Num(n));
+            i += len;
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+
+            tokens.push(Token::Ident(rest[..len].to_owned()));
+            i += len;
+        } else if c == b'\'' || c == b'"' {
+            let mut x = String::new();
+            let mut chars = rest[1..].char_indices();
+
+            let end = loop {
+                let (j, ch) = chars.next()?;
+
+                match ch {
+                    '\\' => x.push(chars.next()?.1),
+                    _ if ch == c as char => break j,
+                    _ => x.push(ch),
+                }
+            };
+
+            tokens.push(Token::Str(x));
+            i += end + 2;
+        } else if c == b'/' && regex_allowed(tokens.last()) {
+            // regex literal, e.g. `/(..)(.)$/` of `s: function(h)`
+            let mut in_class = false;
+            let mut j = i + 1;
+
+            loop {
+                match *bytes.get(j)? {
+                    b'\\' => j += 1,
+                    b'[' => in_class = true,
+                    b']' => in_class = false,
+                    b'/' if !in_class => break,
+                    b'\n' => return None,
+                    _ => {}
+                }
+                j += 1;
+            }
+
+            j += 1;
+
+            while bytes.get(j).is_some_and(u8::is_ascii_alphabetic) {
+                j += 1;
+            }
+
+            tokens.push(Token::Regex);
+            i = j;
+        } else {
+            let punct = PUNCTS.iter().find(|p| rest.starts_with(**p))?;
+
+            tokens.push(Token::Punct(punct));
+            i += punct.len();
+        }
+    }
+
+    Some(tokens)
+}
+
+fn regex_allowed(prev: Option<&Token>) -> bool {
+    match prev {
+        None => true,
+        Some(Token::Punct(p)) => !matches!(*p, ")" | "]" | "}"),
+        Some(Token::Ident(x)) => matches!(x.as_str(), "return" | "typeof" | "case"),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(Vec<(String, Option<Expr>)>),
+    Expr(Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    /// (discriminant, cases); case without test is `default`
+    Switch(Expr, Vec<(Option<Expr>, Vec<Stmt>)>),
+    Block(Vec<Stmt>),
+    Break,
+    Return(Option<Expr>),
+    Empty,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `x = 1`, `x += 1`
+    Assign(&'static str, String, Box<Expr>),
+    /// `x++`, `x--`, `++x`, `--x`; (op, name, prefix)
+    Update(&'static str, String, bool),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(x)) if *x == p)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(x)) if x == keyword)
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        let res = self.is_punct(p);
+        if res {
+            self.pos += 1;
+        }
+        res
+    }
+
+    fn expect(&mut self, p: &str) -> Option<()> {
+        self.eat(p).then_some(())
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Ident(x) => Some(x.clone()),
+            _ => None,
+        }
+    }
+
+    fn block(&mut self) -> Option<Vec<Stmt>> {
+        self.expect("{")?;
+
+        let mut stmts = Vec::new();
+
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+
+        Some(stmts)
+    }
+
+    fn stmt(&mut self) -> Option<Stmt> {
+        let stmt = match self.peek()? {
+            Token::Punct("{") => Stmt::Block(self.block()?),
+            Token::Punct(";") => {
+                self.pos += 1;
+                Stmt::Empty
+            }
+            Token::Ident(x) => match x.as_str() {
+                "var" | "let" | "const" => {
+                    self.pos += 1;
+
+                    let mut decls = Vec::new();
+
+                    loop {
+                        let name = self.ident()?;
+                        let init = if self.eat("=") {
+                            Some(self.expr()?)
+                        } else {
+                            None
+                        };
+
+                        decls.push((name, init));
+
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+
+                    self.eat(";");
+                    Stmt::Var(decls)
+                }
+                "if" => {
+                    self.pos += 1;
+                    self.expect("(")?;
+                    let test = self.expr()?;
+                    self.expect(")")?;
+
+                    let then = Box::new(self.stmt()?);
+
+                    let otherwise = if self.is_keyword("else") {
+                        self.pos += 1;
+                        Some(Box::new(self.stmt()?))
+                    } else {
+                        None
+                    };
+
+                    Stmt::If(test, then, otherwise)
+                }
+                "switch" => {
+                    self.pos += 1;
+                    self.expect("(")?;
+                    let discriminant = self.expr()?;
+                    self.expect(")")?;
+                    self.expect("{")?;
+
+                    let mut cases = Vec::new();
+
+                    while !self.eat("}") {
+                        let test = if self.is_keyword("case") {
+                            self.pos += 1;
+                            Some(self.expr()?)
+                        } else if self.is_keyword("default") {
+                            self.pos += 1;
+                            None
+                        } else {
+                            return None;
+                        };
+
+                        self.expect(":")?;
+
+                        let mut body = Vec::new();
+
+                        while !(self.is_keyword("case")
+                            || self.is_keyword("default")
+                            || self.is_punct("}"))
+                        {
+                            body.push(self.stmt()?);
+                        }
+
+                        cases.push((test, body));
+                    }
+
+                    Stmt::Switch(discriminant, cases)
+                }
+                "break" => {
+                    self.pos += 1;
+                    self.eat(";");
+                    Stmt::Break
+                }
+                "return" => {
+                    self.pos += 1;
+
+                    let value = if self.eat(";") || self.is_punct("}") {
+                        None
+                    } else {
+                        let value = self.expr()?;
+                        self.eat(";");
+                        Some(value)
+                    };
+
+                    Stmt::Return(value)
+                }
+                _ => self.expr_stmt()?,
+            },
+            _ => self.expr_stmt()?,
+        };
+
+        Some(stmt)
+    }
+
+    fn expr_stmt(&mut self) -> Option<Stmt> {
+        let expr = self.expr()?;
+        self.eat(";");
+        Some(Stmt::Expr(expr))
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let expr = self.assign()?;
+
+        // comma operator
+        if self.eat(",") {
+            let rest = self.expr()?;
+            return Some(Expr::Binary(",", Box::new(expr), Box::new(rest)));
+        }
+
+        Some(expr)
+    }
+
+    fn assign(&mut self) -> Option<Expr> {
+        if let [Token::Ident(name), Token::Punct(op), ..] =
+            &self.tokens[self.pos.min(self.tokens.len())..]
+        {
+            if matches!(
+                *op,
+                "=" | "+="
+                    | "-="
+                    | "*="
+                    | "/="
+                    | "%="
+                    | "<<="
+                    | ">>="
+                    | ">>>="
+                    | "&="
+                    | "|="
+                    | "^="
+            ) {
+                let name = name.clone();
+                let op: &'static str = op;
+                self.pos += 2;
+                let value = self.assign()?;
+                return Some(Expr::Assign(op, name, Box::new(value)));
+            }
+        }
+
+        self.cond()
+    }
+
+    fn cond(&mut self) -> Option<Expr> {
+        let test = self.binary(0)?;
+
+        if self.eat("?") {
+            let then = self.assign()?;
+            self.expect(":")?;
+            let otherwise = self.assign()?;
+            return Some(Expr::Cond(
+                Box::new(test),
+                Box::new(then),
+                Box::new(otherwise),
+            ));
+        }
+
+        Some(test)
+    }
+
+    fn binary(&mut self, level: usize) -> Option<Expr> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["===", "!==", "==", "!="],
+            &["<=", ">=", "<", ">"],
+            &["<<", ">>>", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let mut lhs = self.binary(level + 1)?;
+
+        while let Some(Token::Punct(op)) = self.peek() {
+            let Some(op) = ops.iter().find(|x| *x == op) else {
+                break;
+            };
+
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Some(lhs)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        match self.peek()? {
+            Token::Punct(op @ ("!" | "-" | "+" | "~")) => {
+                let op: &'static str = op;
+                self.pos += 1;
+                Some(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            Token::Punct(op @ ("++" | "--")) => {
+                let op: &'static str = op;
+                self.pos += 1;
+                Some(Expr::Update(op, self.ident()?, true))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Option<Expr> {
+        let expr = match self.next()?.clone() {
+            Token::Num(n) => Expr::Num(n),
+            Token::Ident(x) => match x.as_str() {
+                "true" => Expr::Num(1),
+                "false" => Expr::Num(0),
+                _ => Expr::Var(x),
+            },
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                expr
+            }
+            _ => return None,
+        };
+
+        if let Expr::Var(name) = &expr {
+            for op in ["++", "--"] {
+                if self.eat(op) {
+                    return Some(Expr::Update(
+                        if op == "++" { "++" } else { "--" },
+                        name.clone(),
+                        false,
+                    ));
+                }
+            }
+        }
+
+        Some(expr)
+    }
+}
+
+struct Function {
+    arg: String,
+    body: Vec<Stmt>,
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(i64),
+}
+
+impl Function {
+    fn call(&self, arg: i64) -> Option<i64> {
+        let mut env = HashMap::new();
+        env.insert(self.arg.clone(), arg);
+
+        match exec_all(&self.body, &mut env)? {
+            Flow::Return(x) => Some(x),
+            // `return undefined` isn't valid for gg.m
+            _ => None,
+        }
+    }
+}
+
+fn exec_all(stmts: &[Stmt], env: &mut HashMap<String, i64>) -> Option<Flow> {
+    for stmt in stmts {
+        match exec(stmt, env)? {
+            Flow::Normal => {}
+            flow => return Some(flow),
+        }
+    }
+
+    Some(Flow::Normal)
+}
+
+fn exec(stmt: &Stmt, env: &mut HashMap<String, i64>) -> Option<Flow> {
+    let flow = match stmt {
+        Stmt::Var(decls) => {
+            for (name, init) in decls {
+                let value = match init {
+                    Some(init) => eval(init, env)?,
+                    None => 0,
+                };
+
+                env.insert(name.clone(), value);
+            }
+
+            Flow::Normal
+        }
+        Stmt::Expr(expr) => {
+            eval(expr, env)?;
+            Flow::Normal
+        }
+        Stmt::If(test, then, otherwise) => {
+            if eval(test, env)? != 0 {
+                exec(then, env)?
+            } else if let Some(otherwise) = otherwise {
+                exec(otherwise, env)?
+            } else {
+                Flow::Normal
+            }
+        }
+        Stmt::Switch(discriminant, cases) => {
+            let x = eval(discriminant, env)?;
+
+            let mut start = None;
+
+            for (i, (test, _)) in cases.iter().enumerate() {
+                if let Some(test) = test {
+                    if eval(test, env)? == x {
+                        start = Some(i);
+                        break;
+                    }
+                }
+            }
+
+            let start = start.or_else(|| cases.iter().position(|(test, _)| test.is_none()));
+
+            let mut flow = Flow::Normal;
+
+            if let Some(start) = start {
+                // fall through until break
+                for (_, body) in &cases[start..] {
+                    match exec_all(body, env)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        x => {
+                            flow = x;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            flow
+        }
+        Stmt::Block(stmts) => exec_all(stmts, env)?,
+        Stmt::Break => Flow::Break,
+        Stmt::Return(value) => Flow::Return(match value {
+            Some(value) => eval(value, env)?,
+            None => return None,
+        }),
+        Stmt::Empty => Flow::Normal,
+    };
+
+    Some(flow)
+}
+
+fn eval(expr: &Expr, env: &mut HashMap<String, i64>) -> Option<i64> {
+    let value = match expr {
+        Expr::Num(n) => *n,
+        Expr::Var(name) => *env.get(name)?,
+        Expr::Unary(op, x) => {
+            let x = eval(x, env)?;
+
+            match *op {
+                "!" => (x == 0).into(),
+                "-" => x.checked_neg()?,
+                "~" => !(x as i32) as i64,
+                _ => x,
+            }
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = eval(lhs, env)?;
+
+            // short circuit
+            match *op {
+                "&&" if lhs == 0 => return Some(lhs),
+                "||" if lhs != 0 => return Some(lhs),
+                _ => {}
+            }
+
+            let rhs = eval(rhs, env)?;
+
+            binary(op, lhs, rhs)?
+        }
+        Expr::Assign(op, name, value) => {
+            let value = eval(value, env)?;
+
+            let value = match op.strip_suffix('=').filter(|x| !x.is_empty()) {
+                Some(op) => binary(op, *env.get(name)?, value)?,
+                None => value,
+            };
+
+            env.insert(name.clone(), value);
+            value
+        }
+        Expr::Update(op, name, prefix) => {
+            let prev = *env.get(name)?;
+            let next = if *op == "++" { prev + 1 } else { prev - 1 };
+
+            env.insert(name.clone(), next);
+
+            if *prefix {
+                next
+            } else {
+                prev
+            }
+        }
+        Expr::Cond(test, then, otherwise) => {
+            if eval(test, env)? != 0 {
+                eval(then, env)?
+            } else {
+                eval(otherwise, env)?
+            }
+        }
+    };
+
+    Some(value)
+}
+
+fn binary(op: &str, lhs: i64, rhs: i64) -> Option<i64> {
+    let value = match op {
+        "+" => lhs.checked_add(rhs)?,
+        "-" => lhs.checked_sub(rhs)?,
+        "*" => lhs.checked_mul(rhs)?,
+        // only integral quotient is representable
+        "/" => (rhs != 0 && lhs % rhs == 0).then(|| lhs / rhs)?,
+        "%" => lhs.checked_rem(rhs)?,
+        "<<" => ((lhs as i32) << (rhs & 31)) as i64,
+        ">>" => ((lhs as i32) >> (rhs & 31)) as i64,
+        ">>>" => ((lhs as u32) >> (rhs & 31)) as i64,
+        "&" => ((lhs as i32) & (rhs as i32)) as i64,
+        "|" => ((lhs as i32) | (rhs as i32)) as i64,
+        "^" => ((lhs as i32) ^ (rhs as i32)) as i64,
+        "===" | "==" => (lhs == rhs).into(),
+        "!==" | "!=" => (lhs != rhs).into(),
+        "<" => (lhs < rhs).into(),
+        ">" => (lhs > rhs).into(),
+        "<=" => (lhs <= rhs).into(),
+        ">=" => (lhs >= rhs).into(),
+        "&&" | "||" | "," => rhs,
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWITCH: &str = r#"
+var gg = {
+m: function(g) {
+        var o = 0;
+        switch (g) {
+        case 1893:
+        case 3426:
+        o = 1; break;
+        case 7:
+        o = 2; break;
+        }
+        return o;
+},
+s: function(h) { var m = /(..)(.)$/.exec(h); return parseInt(m[2]+m[1], 16).toString(10); },
+b: '1745424004/'
+};
+"#;
+
+    const IF: &str = r#"
+var gg = {
+m: function(g) {
+        var o = 1;
+        if (g === 12) o = 0;
+        if (g === 13) { o = 0; }
+        return o;
+},
+s: function(h) { var m = /(..)(.)$/.exec(h); return parseInt(m[2]+m[1], 16).toString(10); },
+b: "1745424004/"
+};
+"#;
+
+    const ARITHMETIC: &str = r#"
+var gg = {
+m: function(g) {
+        let o = 2;
+        switch (g) {
+        case 100: case 200:
+        o = o - 1;
+        // fall through
+        case 300:
+        o += 1;
+        break;
+        default:
+        o = (g % 2 === 0) ? o * 2 : o;
+        }
+        return o;
+},
+b: '99/'
+};
+"#;
+
+    #[test]
+    fn evaluate_switch() {
+        let gg = parse(SWITCH).unwrap();
+
+        assert_eq!(gg.b, "1745424004");
+        assert_eq!(gg.default, 0);
+        assert_eq!(gg.m(1893), 1);
+        assert_eq!(gg.m(3426), 1);
+        assert_eq!(gg.m(7), 2);
+        assert_eq!(gg.m(8), 0);
+        assert_eq!(gg.m.len(), 3);
+
+        assert_eq!(gg, super::super::parse_gg(SWITCH).unwrap());
+    }
+
+    #[test]
+    fn evaluate_if() {
+        let gg = parse(IF).unwrap();
+
+        assert_eq!(gg.b, "1745424004");
+        assert_eq!(gg.default, 1);
+        assert_eq!(gg.m(12), 0);
+        assert_eq!(gg.m(13), 0);
+        assert_eq!(gg.m(14), 1);
+    }
+
+    #[test]
+    fn evaluate_arithmetic() {
+        let gg = parse(ARITHMETIC).unwrap();
+
+        assert_eq!(gg.b, "99");
+        // odd g keeps 2 and even g doubles to 4, except cases
+        assert_eq!(gg.default, 2);
+        assert_eq!(gg.m(100), 2);
+        assert_eq!(gg.m(300), 3);
+        assert_eq!(gg.m(101), 2);
+        assert_eq!(gg.m(102), 4);
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock, Weak},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use futures::future;
+use itertools::Itertools;
+use regex::Regex;
+use reqwest::{header, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+
+mod js;
+
+use crate::{
+    image::{self, ImageExt, ImageKind},
+    model::File,
+    network::{
+        self,
+        http::{request, BASE_DOMAIN, REFERER},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to parse gg.js")]
+    Parse,
+}
+
+/// Mapping resolved from gg.js
+///
+/// Equality compares `gg.m(g)` for every `g` and `gg.b` ignoring `fetched_at`,
+/// so comparing two fetches tells whether gg.js is rotated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GG {
+    m: HashMap<u32, u32>,
+    b: String,
+    default: u32,
+    fetched_at: DateTime<Utc>,
+}
+
+impl PartialEq for GG {
+    fn eq(&self, other: &Self) -> bool {
+        // `g` is 12 bits of hash, see `image::parse_url`
+        self.b == other.b && (0..0x1000).all(|g| self.m(g) == other.m(g))
+    }
+}
+
+impl Eq for GG {}
+
+impl GG {
+    pub async fn from_hitomi() -> crate::Result<Self> {
+        let resp = request(Method::GET, &format!("https://ltn.{}/gg.js", BASE_DOMAIN)).await?;
+        let status = resp.status();
+
+        if status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+
+            Self::from_js(&text)
+        } else {
+            Err(network::http::Error::Status(status).into())
+        }
+    }
+
+    /// Evaluates `m(g)` of gg.js, or scrapes it by regex if evaluation fails
+    pub fn from_js(s: &str) -> crate::Result<Self> {
+        js::parse(s)
+            .or_else(|| {
+                tracing::warn!("failed to evaluate gg.js, fallback to regex");
+                parse_gg(s)
+            })
+            .ok_or(Error::Parse.into())
+    }
+
+    /// Returns `gg.m(g)`
+    pub fn m(&self, key: u32) -> u32 {
+        self.m.get(&key).copied().unwrap_or(self.default)
+    }
+
+    /// Returns `gg.b` without trailing slash
+    pub fn b(&self) -> &str {
+        &self.b
+    }
+
+    /// Returns `gg.m(g)` for `g` not in table
+    pub fn default(&self) -> u32 {
+        self.default
+    }
+
+    /// Returns `(g, gg.m(g))` which differs from default, in ascending order of `g`
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.m
+            .iter()
+            .map(|(g, m)| (*g, *m))
+            .filter(|(_, m)| *m != self.default)
+            .sorted_unstable()
+    }
+
+    pub fn fetched_at(&self) -> DateTime<Utc> {
+        self.fetched_at
+    }
+
+    /// Requests `HEAD` of original images of `sample` built from this mapping
+    ///
+    /// Use files which are known to exist, so failures mean gg.js isn't parsed correctly.
+    pub async fn verify(&self, client: &reqwest::Client, sample: &[File]) -> Verification {
+        let targets = sample.iter().flat_map(|file| {
+            [ImageExt::Avif, ImageExt::Webp, ImageExt::Jxl]
+                .into_iter()
+                .filter(|ext| file.has(*ext))
+                .map(move |ext| (file, ext))
+        });
+
+        let checks = targets.map(|(file, ext)| async move {
+            let url = image::parse_url(file, ImageKind::Original, ext, self);
+
+            let (url, result) = match url {
+                Ok(url) => {
+                    let resp = client
+                        .head(&url)
+                        .header(header::REFERER, REFERER)
+                        .send()
+                        .await;
+
+                    let result = match resp {
+                        Ok(resp) if resp.status().is_success() => Ok(()),
+                        Ok(resp) => Err(CheckError::Status(resp.status())),
+                        Err(err) => Err(CheckError::Request(err.to_string())),
+                    };
+
+                    (url, result)
+                }
+                Err(err) => (String::new(), Err(CheckError::Url(err.to_string()))),
+            };
+
+            let subdomain = url
+                .strip_prefix("https://")
+                .and_then(|x| x.split_once('.'))
+                .map(|(subdomain, _)| subdomain.to_owned())
+                .unwrap_or_default();
+
+            Check {
+                hash: file.hash.clone(),
+                ext,
+                url,
+                subdomain,
+                b: self.b.clone(),
+                result,
+            }
+        });
+
+        let checks = future::join_all(checks).await;
+
+        for check in checks.iter().filter(|check| check.result.is_err()) {
+            tracing::warn!(
+                url = check.url,
+                subdomain = check.subdomain,
+                b = check.b,
+                "gg.js verification failed: {:?}",
+                check.result
+            );
+        }
+
+        Verification { checks }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CheckError {
+    /// url couldn't be built
+    Url(String),
+    Request(String),
+    Status(StatusCode),
+}
+
+/// Result of requesting an image of sample
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub hash: String,
+    pub ext: ImageExt,
+    pub url: String,
+    /// e.g. `a2`
+    pub subdomain: String,
+    pub b: String,
+    pub result: Result<(), CheckError>,
+}
+
+/// Report of [`GG::verify`]
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub checks: Vec<Check>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| check.result.is_err())
+    }
+
+    /// Returns `(subdomain, b)` combinations which failed at least once
+    pub fn failed_subdomains(&self) -> Vec<(&str, &str)> {
+        self.failures()
+            .map(|check| (check.subdomain.as_str(), check.b.as_str()))
+            .unique()
+            .sorted()
+            .collect()
+    }
+}
+
+/// Lower bound of interval between refreshes of [`GgCache::spawn_updater`]
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shared [`GG`] which is refreshed when it's older than TTL
+///
+/// Hands out cheap [`Arc<GG>`] snapshots, and concurrent refreshes are
+/// deduplicated into one request to hitomi.
+pub struct GgCache {
+    current: RwLock<(Arc<GG>, Instant)>,
+    refreshing: tokio::sync::Mutex<()>,
+    ttl: Duration,
+}
+
+impl GgCache {
+    pub async fn new(ttl: Duration) -> crate::Result<Self> {
+        let gg = GG::from_hitomi().await?;
+
+        Ok(Self::with_gg(gg, ttl))
+    }
+
+    pub fn with_gg(gg: GG, ttl: Duration) -> Self {
+        Self {
+            current: RwLock::new((Arc::new(gg), Instant::now())),
+            refreshing: tokio::sync::Mutex::new(()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns current snapshot without refreshing even if it's expired
+    pub fn snapshot(&self) -> Arc<GG> {
+        self.current.read().unwrap().0.clone()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.current.read().unwrap().1.elapsed() >= self.ttl
+    }
+
+    /// Returns current snapshot, refreshes it before if expired
+    pub async fn get(&self) -> crate::Result<Arc<GG>> {
+        if !self.is_expired() {
+            return Ok(self.snapshot());
+        }
+
+        self.refresh_if(|cache, _| cache.is_expired()).await
+    }
+
+    /// Fetches gg.js from hitomi regardless of TTL
+    pub async fn refresh(&self) -> crate::Result<Arc<GG>> {
+        let stale = self.snapshot();
+
+        self.refresh_stale(&stale).await
+    }
+
+    /// Refreshes if current snapshot is still `stale`
+    ///
+    /// Call this when a request built from `stale` failed (e.g. 403, 404),
+    /// so callers who failed with the same snapshot refresh only once.
+    pub async fn refresh_stale(&self, stale: &Arc<GG>) -> crate::Result<Arc<GG>> {
+        self.refresh_if(|_, current| Arc::ptr_eq(current, stale))
+            .await
+    }
+
+    async fn refresh_if(&self, f: impl Fn(&Self, &Arc<GG>) -> bool) -> crate::Result<Arc<GG>> {
+        let _guard = self.refreshing.lock().await;
+
+        // another caller may have refreshed while waiting lock
+        let current = self.snapshot();
+
+        if !f(self, &current) {
+            return Ok(current);
+        }
+
+        tracing::debug!("refresh gg.js");
+
+        let gg = Arc::new(GG::from_hitomi().await?);
+
+        if gg != current {
+            tracing::debug!(b = gg.b(), "gg.js is rotated");
+        }
+
+        *self.current.write().unwrap() = (gg.clone(), Instant::now());
+
+        Ok(gg)
+    }
+
+    /// Spawns task which refreshes cache whenever TTL is elapsed
+    ///
+    /// Task stops when every `Arc<GgCache>` is dropped.
+    /// Refreshes are at least 10 seconds apart even if TTL is shorter, e.g. zero.
+    pub fn spawn_updater(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let cache: Weak<Self> = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            while let Some(remaining) = cache.upgrade().map(|cache| {
+                let elapsed = cache.current.read().unwrap().1.elapsed();
+                cache.ttl.max(MIN_REFRESH_INTERVAL).saturating_sub(elapsed)
+            }) {
+                tokio::time::sleep(remaining).await;
+
+                let Some(cache) = cache.upgrade() else {
+                    break;
+                };
+
+                if let Err(err) = cache.get().await {
+                    tracing::warn!("failed to refresh gg.js: {err}");
+
+                    // don't spin while hitomi is unreachable, and don't keep cache alive meanwhile
+                    let backoff = cache
+                        .ttl
+                        .clamp(MIN_REFRESH_INTERVAL, Duration::from_secs(60));
+                    drop(cache);
+
+                    tokio::time::sleep(backoff).await;
+                }
+            }
+        })
+    }
+}
+
+fn parse_gg(s: &str) -> Option<GG> {
+    let default_regex = Regex::new(r#"(?si)(var\s|default:)\s*o\s*=\s*(?<default>\d+)"#).unwrap();
+
+    let default_value = default_regex
+        .captures(s)?
+        .name("default")
+        .unwrap()
+        .as_str()
+        .parse::<u32>()
+        .unwrap();
+
+    //
+
+    let case_regex = Regex::new(r#"(?si)case\s+(?<key>\d+):\s+o?\s*=?\s*(?<value>\d+)?"#).unwrap();
+
+    let mut m = HashMap::new();
+    let mut keys = Vec::new();
+
+    for caps in case_regex.captures_iter(s) {
+        let key = caps["key"].parse::<u32>().unwrap();
+
+        keys.push(key);
+
+        if let Some(value) = caps.name("value") {
+            let value = value.as_str().parse::<u32>().unwrap();
+
+            for key in keys.drain(..) {
+                m.insert(key, value);
+            }
+        }
+    }
+
+    //
+
+    let cond_regex = Regex::new(
+        r#"(?si)if\s*[(]g\s*===\s*(?<key>\d+)[)]\s*[{]?\s*o\s*=\s*(?<value>\d+);?\s*[}]?"#,
+    )
+    .unwrap();
+
+    for caps in cond_regex.captures_iter(s) {
+        let key = caps["key"].parse::<u32>().unwrap();
+        let value = caps["value"].parse::<u32>().unwrap();
+
+        m.entry(key)
+            .and_modify(|prev| *prev = value)
+            .or_insert(value);
+    }
+
+    //
+
+    let b_regex = Regex::new(r#"(?si)b:\s*["'](?<b>.+?)["']"#).unwrap();
+
+    let b = &b_regex.captures(s)?["b"];
+
+    Some(GG {
+        m,
+        b: b.strip_suffix('/').unwrap_or(b).to_owned(),
+        default: default_value,
+        fetched_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_gg() {
+        let gg = GG::from_hitomi().await;
+
+        assert!(gg.is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_gg() {
+        let gallery = crate::gallery::parse(3014301).await.unwrap().unwrap();
+
+        let gg = GG::from_hitomi().await.unwrap();
+
+        let sample = gallery
+            .files
+            .into_iter()
+            .take(3)
+            .map(|(_, file)| file)
+            .collect::<Vec<_>>();
+
+        let verification = gg.verify(network::http::client(), &sample).await;
+
+        assert!(
+            verification.is_ok(),
+            "{:?}",
+            verification.failed_subdomains()
+        );
+
+        // broken mapping
+        let broken = GG {
+            b: "0".to_owned(),
+            ..gg
+        };
+
+        let verification = broken.verify(network::http::client(), &sample).await;
+
+        assert!(!verification.is_ok());
+    }
+
+    #[test]
+    fn serialize_gg() {
+        let js = "var gg = { m: function(g) { var o = 0; switch (g) { case 1: case 2: o = 1; break; } return o; }, b: '123/' };";
+
+        let gg = GG::from_js(js).unwrap();
+
+        assert_eq!(gg.b(), "123");
+        assert_eq!(gg.default(), 0);
+        assert_eq!(gg.iter().collect::<Vec<_>>(), [(1, 1), (2, 1)]);
+
+        let json = serde_json::to_string(&gg).unwrap();
+        let deserialized = serde_json::from_str::<GG>(&json).unwrap();
+
+        assert_eq!(deserialized, gg);
+        assert_eq!(deserialized.fetched_at(), gg.fetched_at());
+
+        let rotated = GG::from_js(&js.replace("123/", "124/")).unwrap();
+
+        assert_ne!(rotated, gg);
+
+        // same mapping with explicit default case
+        let explicit = GG {
+            m: [(1, 1), (2, 1), (3, 0)].into_iter().collect(),
+            ..gg.clone()
+        };
+
+        assert_eq!(explicit, gg);
+        assert_eq!(explicit.iter().collect::<Vec<_>>(), [(1, 1), (2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_gg_cache() {
+        let cache = GgCache::new(Duration::from_secs(60)).await.unwrap();
+
+        let stale = cache.snapshot();
+
+        let (a, b) = tokio::join!(cache.refresh_stale(&stale), cache.refresh_stale(&stale));
+        let (a, b) = (a.unwrap(), b.unwrap());
+
+        assert!(!Arc::ptr_eq(&stale, &a));
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::model::File;
+
+use super::{Image, ImageExt, ImageStream};
+
+/// count of leading bytes kept to sniff format
+const HEAD_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("length mismatch: expected = {expected}; actual = {actual}")]
+    Length { expected: u64, actual: u64 },
+
+    #[error("format mismatch: expected = {expected}; found = {found:?}")]
+    Format {
+        expected: ImageExt,
+        found: Option<ImageFormat>,
+    },
+
+    #[error("hash mismatch: expected = {expected}; actual = {actual}")]
+    Hash { expected: String, actual: String },
+}
+
+/// Format of image detected from magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Avif,
+    Webp,
+    Jxl,
+    Jpeg,
+    Png,
+    Gif,
+}
+
+impl ImageFormat {
+    /// Returns format of file extension, e.g. `jpg`
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let format = match ext.to_ascii_lowercase().as_str() {
+            "avif" => Self::Avif,
+            "webp" => Self::Webp,
+            "jxl" => Self::Jxl,
+            "jpg" | "jpeg" | "jfif" => Self::Jpeg,
+            "png" => Self::Png,
+            "gif" => Self::Gif,
+            _ => return None,
+        };
+
+        Some(format)
+    }
+
+    /// Returns media type, e.g. `image/jpeg`
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+            Self::Jxl => "image/jxl",
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Gif => "image/gif",
+        }
+    }
+}
+
+/// Detects format of image from leading bytes of `buf`
+pub fn sniff(buf: &[u8]) -> Option<ImageFormat> {
+    const JXL_CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+    let format = match buf {
+        [0xff, 0xd8, 0xff, ..] => ImageFormat::Jpeg,
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => ImageFormat::Png,
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => ImageFormat::Gif,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => ImageFormat::Webp,
+        [0xff, 0x0a, ..] => ImageFormat::Jxl,
+        _ if buf.starts_with(JXL_CONTAINER) => ImageFormat::Jxl,
+        [a, b, c, d, b'f', b't', b'y', b'p', ..] => {
+            // major brand and compatible brands of `ftyp` box
+            let size = u32::from_be_bytes([*a, *b, *c, *d]) as usize;
+            let brands = buf.get(8..size.min(buf.len()))?;
+
+            let is_avif = brands
+                .chunks_exact(4)
+                .enumerate()
+                // skip minor version
+                .filter(|(i, _)| *i != 1)
+                .any(|(_, brand)| brand == b"avif" || brand == b"avis");
+
+            if !is_avif {
+                return None;
+            }
+
+            ImageFormat::Avif
+        }
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+/// Checks to run over downloaded image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verify {
+    /// received bytes equal to `Content-Length`
+    pub length: bool,
+    /// magic bytes match requested [`ImageExt`]
+    pub format: bool,
+    /// SHA-256 of received bytes equals to [`File::hash`]
+    ///
+    /// Only [`ImageExt::Original`] is checked,
+    /// because the others are encoded from original by hitomi.
+    pub hash: bool,
+}
+
+impl Verify {
+    pub fn all() -> Self {
+        Self {
+            length: true,
+            format: true,
+            hash: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            length: false,
+            format: false,
+            hash: false,
+        }
+    }
+}
+
+impl Default for Verify {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Sink which hashes image bytes as they are received
+pub struct Verifier {
+    verify: Verify,
+    ext: ImageExt,
+    expected_format: Option<ImageFormat>,
+    expected_hash: String,
+    expected_length: Option<u64>,
+    head: Vec<u8>,
+    length: u64,
+    hasher: Sha256,
+}
+
+impl Verifier {
+    /// `expected_length` is length of whole image, e.g. `Content-Length`
+    pub fn new(file: &File, ext: ImageExt, expected_length: Option<u64>, verify: Verify) -> Self {
+        let expected_format = match ext {
+            ImageExt::Avif => Some(ImageFormat::Avif),
+            ImageExt::Webp => Some(ImageFormat::Webp),
+            ImageExt::Jxl => Some(ImageFormat::Jxl),
+            ImageExt::Original => file.original_ext().and_then(ImageFormat::from_extension),
+            ImageExt::Png => Some(ImageFormat::Png),
+            ImageExt::Jpeg => Some(ImageFormat::Jpeg),
+        };
+
+        Self {
+            verify,
+            ext,
+            expected_format,
+            expected_hash: file.hash.to_ascii_lowercase(),
+            expected_length,
+            head: Vec::with_capacity(HEAD_LEN),
+            length: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if self.head.len() < HEAD_LEN {
+            let n = (HEAD_LEN - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..n]);
+        }
+
+        if self.checks_hash() {
+            self.hasher.update(chunk);
+        }
+
+        self.length += chunk.len() as u64;
+    }
+
+    fn checks_hash(&self) -> bool {
+        self.verify.hash && self.ext == ImageExt::Original
+    }
+
+    pub fn finish(self) -> Result<(), IntegrityError> {
+        if let (true, Some(expected)) = (self.verify.length, self.expected_length) {
+            if expected != self.length {
+                return Err(IntegrityError::Length {
+                    expected,
+                    actual: self.length,
+                });
+            }
+        }
+
+        if self.verify.format {
+            let found = sniff(&self.head);
+
+            // unknown original format can't be checked
+            if self.expected_format.is_some() && found != self.expected_format {
+                return Err(IntegrityError::Format {
+                    expected: self.ext,
+                    found,
+                });
+            }
+        }
+
+        if self.checks_hash() {
+            let actual = format!("{:x}", self.hasher.finalize());
+
+            if actual != self.expected_hash {
+                return Err(IntegrityError::Hash {
+                    expected: self.expected_hash,
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Image {
+    /// Checks buffered image against `file`
+    pub fn verify(&self, file: &File, verify: Verify) -> Result<(), IntegrityError> {
+        let verify = Verify {
+            // whole body is already received
+            length: false,
+            ..verify
+        };
+
+        let mut verifier = Verifier::new(file, self.ext, None, verify);
+        verifier.update(&self.buf);
+        verifier.finish()
+    }
+}
+
+impl ImageStream {
+    /// Returns [`Verifier`] which expects length of this stream
+    ///
+    /// Feed bytes already received before passing it to [`ImageStream::verify_with`]
+    /// if stream is resumed.
+    pub fn verifier(&self, file: &File, verify: Verify) -> Verifier {
+        let expected_length = self
+            .total_length
+            .or(self.content_length.map(|x| x + self.offset));
+
+        Verifier::new(file, self.ext, expected_length, verify)
+    }
+
+    /// Checks image while streaming
+    ///
+    /// Yields [`IntegrityError`] as the last item if check fails.
+    pub fn verify(self, file: &File, verify: Verify) -> Self {
+        let verifier = self.verifier(file, verify);
+
+        self.verify_with(verifier)
+    }
+
+    /// Same as [`ImageStream::verify`] with prepared `verifier`
+    pub fn verify_with(mut self, verifier: Verifier) -> Self {
+        let inner = std::mem::replace(&mut self.inner, futures::stream::empty().boxed());
+
+        self.inner = Verifying {
+            inner,
+            verifier: Some(verifier),
+        }
+        .boxed();
+
+        self
+    }
+}
+
+struct Verifying<S> {
+    inner: S,
+    verifier: Option<Verifier>,
+}
+
+impl<S> Stream for Verifying<S>
+where
+    S: Stream<Item = crate::Result<Bytes>> + Unpin,
+{
+    type Item = crate::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.verifier.is_none() {
+            return Poll::Ready(None);
+        }
+
+        match self.inner.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(verifier) = self.verifier.as_mut() {
+                    verifier.update(&chunk);
+                }
+
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                self.verifier = None;
+
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Ready(None) => {
+                let res = self.verifier.take().unwrap().finish();
+
+                Poll::Ready(res.err().map(|err| Err(super::Error::from(err).into())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, content: &[u8]) -> File {
+        File {
+            name: name.to_owned(),
+            ..crate::tests::file(&format!("{:x}", Sha256::digest(content)))
+        }
+    }
+
+    #[test]
+    fn sniff_format() {
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), Some(ImageFormat::Jpeg));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n"), Some(ImageFormat::Png));
+        assert_eq!(sniff(b"GIF89a"), Some(ImageFormat::Gif));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(ImageFormat::Webp));
+        assert_eq!(sniff(b"\xff\x0a"), Some(ImageFormat::Jxl));
+        assert_eq!(
+            sniff(b"\0\0\0\x0cJXL \r\n\x87\n\0\0\0\x14ftypjxl "),
+            Some(ImageFormat::Jxl)
+        );
+        assert_eq!(
+            sniff(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"),
+            Some(ImageFormat::Avif)
+        );
+        assert_eq!(
+            sniff(b"\0\0\0\x18ftypmif1\0\0\0\0mif1avis"),
+            Some(ImageFormat::Avif)
+        );
+        assert_eq!(sniff(b"\0\0\0\x14ftypisom\0\0\0\0isom"), None);
+        assert_eq!(sniff(b"<html>"), None);
+    }
+
+    #[test]
+    fn verify_image() {
+        let content = b"\xff\xd8\xff\xe0hello";
+        let file = file("01.jpg", content);
+
+        let mut verifier = Verifier::new(&file, ImageExt::Original, Some(9), Verify::all());
+        verifier.update(&content[..3]);
+        verifier.update(&content[3..]);
+        assert!(verifier.finish().is_ok());
+
+        // truncated
+        let mut verifier = Verifier::new(&file, ImageExt::Original, Some(9), Verify::all());
+        verifier.update(&content[..5]);
+        assert!(matches!(
+            verifier.finish(),
+            Err(IntegrityError::Length {
+                expected: 9,
+                actual: 5
+            })
+        ));
+
+        // corrupted
+        let mut verifier = Verifier::new(&file, ImageExt::Original, None, Verify::all());
+        verifier.update(b"\xff\xd8\xff\xe0hellO");
+        assert!(matches!(
+            verifier.finish(),
+            Err(IntegrityError::Hash { .. })
+        ));
+
+        // not avif
+        let mut verifier = Verifier::new(&file, ImageExt::Avif, None, Verify::all());
+        verifier.update(b"<html></html>");
+        assert!(matches!(
+            verifier.finish(),
+            Err(IntegrityError::Format {
+                expected: ImageExt::Avif,
+                found: None
+            })
+        ));
+    }
+}
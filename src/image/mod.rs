@@ -0,0 +1,582 @@
+use std::fmt::Display;
+
+use bytes::Bytes;
+use itertools::Itertools;
+use reqwest::{Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+mod integrity;
+mod probe;
+mod stream;
+#[cfg(feature = "transcode")]
+mod transcode;
+
+pub use integrity::*;
+pub use probe::*;
+pub use stream::*;
+#[cfg(feature = "transcode")]
+pub use transcode::*;
+
+use crate::{
+    gg::{GgCache, GG},
+    model::File,
+    network::{
+        self,
+        http::{request, ContentRange, BASE_DOMAIN, REFERER},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("hasn't image ext: {0:?}")]
+    HasNotImage(ImageExt),
+
+    #[error("hasn't thumbnail of image ext: {0:?}")]
+    HasNotThumbnail(ImageExt),
+
+    #[error("hasn't any image of exts: {0:?}")]
+    HasNotAnyImage(Vec<ImageExt>),
+
+    #[error("can't parsed u32 from hash: hash = {0}; hex = {1}")]
+    ParseU32FromHash(String, String),
+
+    #[error("can't parsed prefix subdomain: x = {0}")]
+    ParsePrefixOfSubdomain(u32),
+
+    #[error("can't parsed url: {0}: {1}")]
+    ParseUrl(String, url::ParseError),
+
+    #[error("unexpected content range: requested = {0}; received = {1:?}")]
+    ContentRange(u64, Option<ContentRange>),
+
+    #[error("integrity: {0}")]
+    Integrity(#[from] IntegrityError),
+
+    #[cfg(feature = "transcode")]
+    #[error("can't transcode image ext: {0:?}")]
+    UnsupportedTranscode(ImageExt),
+
+    #[cfg(feature = "transcode")]
+    #[error("transcode: {0}")]
+    Transcode(#[from] ::image::ImageError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageKind {
+    /// `{ext}smalltn`, used in small listings
+    SmallThumbnail,
+    /// `{ext}smallbigtn`, used in gallery blocks
+    SmallBigThumbnail,
+    /// `{ext}bigtn`, used in gallery pages and readers
+    Thumbnail,
+    Original,
+}
+
+impl ImageKind {
+    /// Returns size of thumbnail as used in path, `None` if original
+    fn thumbnail_size(&self) -> Option<&'static str> {
+        match self {
+            ImageKind::SmallThumbnail => Some("smalltn"),
+            ImageKind::SmallBigThumbnail => Some("smallbigtn"),
+            ImageKind::Thumbnail => Some("bigtn"),
+            ImageKind::Original => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageExt {
+    Avif,
+    Webp,
+    /// Served from `j` subdomains
+    ///
+    /// Unverified: subdomain is inferred from `a`/`w` of AVIF/WebP, not pinned to a recorded url.
+    Jxl,
+    /// Uploaded file served from `images` path, e.g. jpg, png and gif
+    ///
+    /// Its extension comes from [`File::name`], see [`File::original_ext`].
+    ///
+    /// Unverified: `b` subdomains and `images` path are not pinned to a recorded url.
+    Original,
+    /// Produced by transcoding, hitomi doesn't serve it
+    Png,
+    /// Produced by transcoding, hitomi doesn't serve it
+    Jpeg,
+}
+
+impl ImageExt {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageExt::Avif => "avif",
+            ImageExt::Webp => "webp",
+            ImageExt::Jxl => "jxl",
+            ImageExt::Original => "original",
+            ImageExt::Png => "png",
+            ImageExt::Jpeg => "jpg",
+        }
+    }
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for ImageExt {
+    fn default() -> Self {
+        Self::Avif
+    }
+}
+
+impl AsRef<str> for ImageExt {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for ImageExt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
+
+/// Url of image with headers required to request it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageUrl {
+    pub url: Url,
+    /// `Referer` header, hitomi rejects requests without it
+    pub referer: &'static str,
+}
+
+impl ImageUrl {
+    pub fn as_str(&self) -> &str {
+        self.url.as_str()
+    }
+}
+
+impl AsRef<str> for ImageUrl {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for ImageUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.url.fmt(f)
+    }
+}
+
+pub struct Image {
+    pub kind: ImageKind,
+    pub ext: ImageExt,
+    pub url: String,
+    pub buf: Bytes,
+}
+
+impl Image {
+    /// Returns file extension of image, e.g. `avif`, `jpg`
+    ///
+    /// Unlike `ext`, it's resolved for [`ImageExt::Original`].
+    pub fn extension(&self) -> &str {
+        extension_of(&self.url, &self.ext)
+    }
+}
+
+fn extension_of<'a>(url: &'a str, ext: &'a ImageExt) -> &'a str {
+    match ext {
+        ImageExt::Original => url.rsplit_once('.').map(|(_, x)| x).unwrap_or(ext.as_str()),
+        _ => ext.as_str(),
+    }
+}
+
+pub async fn download(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+) -> crate::Result<Image> {
+    let image_url = parse_url(file, kind, ext, gg)?;
+
+    let resp = request(Method::GET, &image_url).await?;
+
+    let status = resp.status();
+
+    if status.is_success() {
+        let buf = resp.bytes().await?;
+
+        Ok(Image {
+            kind,
+            ext,
+            url: image_url,
+            buf,
+        })
+    } else {
+        Err(network::http::Error::Status(status).into())
+    }
+}
+
+/// Downloads image as the first ext of `exts` which `file` has
+///
+/// Falls back to the next ext if hitomi responds 404.
+/// [`Image::ext`] is the ext actually delivered.
+///
+/// ## Errors
+///
+/// - [`Error::HasNotAnyImage`] if `file` has none of `exts`
+/// - 404 of the last ext if every ext responds 404
+pub async fn download_preferred(
+    file: &File,
+    kind: ImageKind,
+    exts: &[ImageExt],
+    gg: &GG,
+) -> crate::Result<Image> {
+    let mut not_found = None;
+
+    for ext in available_exts(file, kind, exts) {
+        match download(file, kind, ext, gg).await {
+            Err(err @ crate::Error::Http(network::http::Error::Status(StatusCode::NOT_FOUND))) => {
+                tracing::debug!(?ext, "not found, fallback to next ext");
+
+                not_found = Some(err);
+            }
+            res => return res,
+        }
+    }
+
+    Err(not_found.unwrap_or_else(|| Error::HasNotAnyImage(exts.to_vec()).into()))
+}
+
+/// Returns exts of `exts` which `file` has as `kind`, in the same order
+pub fn available_exts<'a>(
+    file: &'a File,
+    kind: ImageKind,
+    exts: &'a [ImageExt],
+) -> impl Iterator<Item = ImageExt> + 'a {
+    exts.iter().copied().filter(move |ext| {
+        let has_thumbnail = kind == ImageKind::Original || *ext != ImageExt::Original;
+
+        has_thumbnail && file.has(*ext)
+    })
+}
+
+/// Downloads image with a snapshot of `gg`
+///
+/// If hitomi responds 403 or 404, gg.js may be rotated,
+/// so refreshes `gg` and retries once.
+pub async fn download_cached(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GgCache,
+) -> crate::Result<Image> {
+    let snapshot = gg.get().await?;
+
+    match download(file, kind, ext, &snapshot).await {
+        Err(crate::Error::Http(network::http::Error::Status(
+            StatusCode::FORBIDDEN | StatusCode::NOT_FOUND,
+        ))) => {
+            let gg = gg.refresh_stale(&snapshot).await?;
+
+            download(file, kind, ext, &gg).await
+        }
+        res => res,
+    }
+}
+
+/// Builds url of image without downloading it
+///
+/// Pass [`ImageUrl::referer`] as `Referer` header when requesting it with another http client.
+pub fn url(file: &File, kind: ImageKind, ext: ImageExt, gg: &GG) -> Result<ImageUrl, Error> {
+    let url = parse_url(file, kind, ext, gg)?;
+
+    Ok(ImageUrl {
+        url: url.parse().map_err(|err| Error::ParseUrl(url, err))?,
+        referer: REFERER,
+    })
+}
+
+pub(crate) fn parse_url(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+) -> Result<String, Error> {
+    // validate image ext exists on hitomi
+    if !file.has(ext) {
+        return Err(Error::HasNotImage(ext));
+    }
+
+    let (base_subdomain, dir, extension) = match ext {
+        ImageExt::Webp => ('w', "", ext.as_str()),
+        ImageExt::Avif => ('a', "", ext.as_str()),
+        ImageExt::Jxl => ('j', "", ext.as_str()),
+        // `has` guarantees extension
+        ImageExt::Original => ('b', "images/", file.original_ext().unwrap()),
+        ImageExt::Png | ImageExt::Jpeg => return Err(Error::HasNotImage(ext)),
+    };
+
+    tracing::debug!(?base_subdomain);
+
+    // var r = /\/[0-9a-f]{61}([0-9a-f]{2})([0-9a-f])/;
+    let postfix = file.hash[file.hash.len() - 3..]
+        .chars()
+        .collect_array::<3>()
+        .unwrap();
+
+    tracing::debug!(?file.hash);
+    tracing::debug!(?postfix);
+
+    let parsed_hex_from_hash = format!("{}{}{}", postfix[2], postfix[0], postfix[1]);
+
+    tracing::debug!(?parsed_hex_from_hash);
+
+    let g = u32::from_str_radix(&parsed_hex_from_hash, 16)
+        .map_err(|_| Error::ParseU32FromHash(file.hash.clone(), parsed_hex_from_hash.clone()))?;
+
+    let m = gg.m(g);
+
+    tracing::debug!(?g);
+
+    let image_url = match kind.thumbnail_size() {
+        Some(_) if ext == ImageExt::Original => return Err(Error::HasNotThumbnail(ext)),
+        Some(size) => {
+            let prefix_of_subdomain =
+                char::from_u32(97 + m).ok_or(Error::ParsePrefixOfSubdomain(m))?;
+
+            let subdomain = format!("{}tn", prefix_of_subdomain);
+
+            tracing::debug!(?subdomain);
+
+            format!(
+                "https://{}.{BASE_DOMAIN}/{ext}{size}/{}/{}{}/{}.{ext}",
+                subdomain, postfix[2], postfix[0], postfix[1], file.hash
+            )
+        }
+        None => {
+            let subdomain = format!("{}{}", base_subdomain, 1 + m);
+
+            tracing::debug!(?subdomain);
+
+            format!(
+                "https://{}.{BASE_DOMAIN}/{dir}{}/{}/{}.{extension}",
+                subdomain,
+                gg.b(),
+                g,
+                file.hash,
+            )
+        }
+    };
+
+    tracing::debug!(?image_url);
+
+    Ok(image_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use futures::stream::{self, StreamExt};
+
+    use crate::{
+        gallery,
+        nozomi::{self, Language},
+        tests::tracing,
+    };
+
+    use super::*;
+
+    #[test]
+    fn build_url() {
+        let gg = GG::from_js(
+            "var gg = { m: function(g) { var o = 0; switch (g) { case 2594: o = 1; break; } return o; }, b: '1745424004/' };",
+        )
+        .unwrap();
+
+        let file = File {
+            has_webp: true,
+            has_avif: true,
+            has_jxl: false,
+            width: 1280,
+            height: 1810,
+            hash: "9a6a6d4d3e1bd8e1e8e0b7dd8c7c7a16b5e4b8a6e8b1b2b9a5c7f3e2d1c0f22a".to_owned(),
+            name: "01.jpg".to_owned(),
+        };
+
+        let original = url(&file, ImageKind::Original, ImageExt::Avif, &gg).unwrap();
+
+        // g = 0xa22
+        assert_eq!(
+            original.as_str(),
+            format!(
+                "https://a2.{BASE_DOMAIN}/1745424004/2594/{}.avif",
+                file.hash
+            )
+        );
+        assert_eq!(original.referer, REFERER);
+
+        let thumbnail = file.url(ImageKind::Thumbnail, ImageExt::Webp, &gg).unwrap();
+
+        assert_eq!(
+            thumbnail.as_str(),
+            format!(
+                "https://btn.{BASE_DOMAIN}/webpbigtn/a/22/{}.webp",
+                file.hash
+            )
+        );
+
+        let small = url(&file, ImageKind::SmallThumbnail, ImageExt::Avif, &gg).unwrap();
+
+        assert_eq!(
+            small.as_str(),
+            format!(
+                "https://btn.{BASE_DOMAIN}/avifsmalltn/a/22/{}.avif",
+                file.hash
+            )
+        );
+
+        let small_big = url(&file, ImageKind::SmallBigThumbnail, ImageExt::Avif, &gg).unwrap();
+
+        assert_eq!(
+            small_big.as_str(),
+            format!(
+                "https://btn.{BASE_DOMAIN}/avifsmallbigtn/a/22/{}.avif",
+                file.hash
+            )
+        );
+
+        let original = url(&file, ImageKind::Original, ImageExt::Original, &gg).unwrap();
+
+        assert_eq!(
+            original.as_str(),
+            format!(
+                "https://b2.{BASE_DOMAIN}/images/1745424004/2594/{}.jpg",
+                file.hash
+            )
+        );
+
+        assert!(matches!(
+            url(&file, ImageKind::Original, ImageExt::Jxl, &gg),
+            Err(Error::HasNotImage(ImageExt::Jxl))
+        ));
+
+        assert!(matches!(
+            url(&file, ImageKind::Thumbnail, ImageExt::Original, &gg),
+            Err(Error::HasNotThumbnail(ImageExt::Original))
+        ));
+
+        let file = File {
+            has_jxl: true,
+            ..file
+        };
+
+        let exts = [ImageExt::Jxl, ImageExt::Original, ImageExt::Avif];
+
+        assert_eq!(
+            available_exts(&file, ImageKind::Thumbnail, &exts).collect::<Vec<_>>(),
+            [ImageExt::Jxl, ImageExt::Avif]
+        );
+        assert_eq!(
+            available_exts(&file, ImageKind::Original, &exts).collect::<Vec<_>>(),
+            exts
+        );
+
+        let jxl = url(&file, ImageKind::Original, ImageExt::Jxl, &gg).unwrap();
+
+        assert_eq!(
+            jxl.as_str(),
+            format!("https://j2.{BASE_DOMAIN}/1745424004/2594/{}.jxl", file.hash)
+        );
+    }
+
+    #[tokio::test]
+    async fn download_image() {
+        tracing();
+
+        let ids = nozomi::parse(Language::Korean, 1, 25).await.unwrap();
+
+        let id = ids[2];
+
+        let gallery_dir = format!("./sample/images/{id}");
+        std::fs::create_dir_all(&gallery_dir).unwrap();
+
+        let gallery = gallery::parse(id).await.unwrap().unwrap();
+
+        let (_, file) = &gallery.files[0];
+
+        let gg = GG::from_hitomi().await.unwrap();
+
+        // avif thumbnail
+        let avif_thumbnail = {
+            let gg = &gg;
+            let gallery_dir = &*gallery_dir;
+            async move {
+                let image = download(file, ImageKind::Thumbnail, ImageExt::Avif, gg)
+                    .await
+                    .unwrap();
+
+                let name = format!("{}/thumbnail.{}", gallery_dir, image.ext.as_str());
+                let mut f = std::fs::File::create(name).unwrap();
+
+                f.write_all(&image.buf).unwrap();
+            }
+        };
+
+        // avif original
+        let avif_original = {
+            let gg = &gg;
+            let gallery_dir = &*gallery_dir;
+            async move {
+                let image = download(file, ImageKind::Original, ImageExt::Avif, gg)
+                    .await
+                    .unwrap();
+
+                let name = format!("{}/original.{}", gallery_dir, image.ext.as_str());
+                let mut f = std::fs::File::create(name).unwrap();
+
+                f.write_all(&image.buf).unwrap();
+            }
+        };
+
+        tokio::join!(avif_thumbnail, avif_original);
+    }
+
+    #[tokio::test]
+    #[ignore = "use many network resource"]
+    async fn download_images() {
+        tracing();
+
+        // let ids = nozomi::parse(Language::Korean, 1, 25).await.unwrap();
+
+        // let id = ids[2];
+        let id = 3282933;
+
+        let gg = GG::from_hitomi().await.unwrap();
+
+        let gallery_dir = format!("./sample/images/{id}");
+        std::fs::create_dir_all(&gallery_dir).unwrap();
+
+        let gallery = gallery::parse(id).await.unwrap().unwrap();
+
+        std::fs::write(
+            format!("{}/files.json", gallery_dir),
+            serde_json::to_vec_pretty(&gallery).unwrap(),
+        )
+        .unwrap();
+
+        let gallery_dir = &gallery_dir;
+        stream::iter(gallery.files.iter().take(100))
+            .enumerate()
+            .for_each(|(p, (_, file))| {
+                let gg = &gg;
+                async move {
+                    let image = download(file, ImageKind::Original, ImageExt::Avif, gg)
+                        .await
+                        .unwrap();
+
+                    let name = format!("{}/{p}.{}", gallery_dir, image.ext.as_str());
+                    let mut f = std::fs::File::create(name).unwrap();
+
+                    f.write_all(&image.buf).unwrap();
+                }
+            })
+            .await;
+    }
+}
@@ -0,0 +1,619 @@
+use crate::model::File;
+
+use super::{sniff, Image, ImageFormat};
+
+/// Format and dimensions read from header of image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub animated: bool,
+}
+
+impl ImageInfo {
+    /// Returns whether dimensions equal to `width` and `height` of gallery metadata
+    ///
+    /// Only originals are comparable, thumbnails are resized by hitomi.
+    pub fn matches(&self, file: &File) -> bool {
+        self.width as usize == file.width && self.height as usize == file.height
+    }
+}
+
+impl Image {
+    /// See [`probe`]
+    pub fn probe(&self) -> Option<ImageInfo> {
+        probe(&self.buf)
+    }
+}
+
+/// Reads format, dimensions and whether animated from header of image without decoding it
+///
+/// Supports AVIF, WebP and JXL which hitomi serves, and JPEG, PNG and GIF of originals.
+///
+/// Returns `None` if format is unknown or header is broken.
+pub fn probe(buf: &[u8]) -> Option<ImageInfo> {
+    let format = sniff(buf)?;
+
+    let (width, height, animated) = match format {
+        ImageFormat::Avif => avif(buf)?,
+        ImageFormat::Webp => webp(buf)?,
+        ImageFormat::Jxl => jxl(buf)?,
+        ImageFormat::Jpeg => jpeg(buf)?,
+        ImageFormat::Png => png(buf)?,
+        ImageFormat::Gif => gif(buf)?,
+    };
+
+    Some(ImageInfo {
+        format,
+        width,
+        height,
+        animated,
+    })
+}
+
+fn u16_be(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u16_le(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_be(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_le(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u24_le(buf: &[u8], at: usize) -> Option<u32> {
+    let x = buf.get(at..at + 3)?;
+    Some(u32::from(x[0]) | u32::from(x[1]) << 8 | u32::from(x[2]) << 16)
+}
+
+/// Iterates `(type, payload)` of ISOBMFF boxes in `buf`
+fn boxes(buf: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut rest = buf;
+
+    std::iter::from_fn(move || {
+        let size = u32_be(rest, 0)? as u64;
+        let kind: [u8; 4] = rest.get(4..8)?.try_into().ok()?;
+
+        let (header, size) = match size {
+            // extends to end
+            0 => (8, rest.len() as u64),
+            1 => (16, u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?)),
+            _ => (8, size),
+        };
+
+        let size = usize::try_from(size).ok()?;
+        let payload = rest.get(header..size)?;
+
+        rest = &rest[size..];
+
+        Some((kind, payload))
+    })
+}
+
+fn find_box<'a>(buf: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(buf)
+        .find(|(k, _)| k == kind)
+        .map(|(_, payload)| payload)
+}
+
+/// `ispe` of primary item in `meta/iprp/ipco`
+fn avif(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let ftyp = find_box(buf, b"ftyp")?;
+
+    let animated =
+        ftyp.chunks_exact(4).any(|brand| brand == b"avis") || find_box(buf, b"moov").is_some();
+
+    // full box
+    let meta = find_box(buf, b"meta")?.get(4..)?;
+    let iprp = find_box(meta, b"iprp")?;
+    let ipco = find_box(iprp, b"ipco")?;
+
+    let properties = boxes(ipco).collect::<Vec<_>>();
+
+    let ispe = |payload: &[u8]| Some((u32_be(payload, 4)?, u32_be(payload, 8)?));
+
+    let primary = primary_properties(meta, iprp).and_then(|indices| {
+        indices
+            .into_iter()
+            .find_map(|i| match properties.get(i.checked_sub(1)?) {
+                Some(([b'i', b's', b'p', b'e'], payload)) => ispe(payload),
+                _ => None,
+            })
+    });
+
+    // fallback to the largest one, e.g. grid
+    let (width, height) = primary.or_else(|| {
+        properties
+            .iter()
+            .filter(|(kind, _)| kind == b"ispe")
+            .filter_map(|(_, payload)| ispe(payload))
+            .max_by_key(|(w, h)| u64::from(*w) * u64::from(*h))
+    })?;
+
+    Some((width, height, animated))
+}
+
+/// Returns 1-based indices of properties associated with primary item by `pitm` and `ipma`
+fn primary_properties(meta: &[u8], iprp: &[u8]) -> Option<Vec<usize>> {
+    let pitm = find_box(meta, b"pitm")?;
+
+    let primary = match pitm.first()? {
+        0 => u32::from(u16_be(pitm, 4)?),
+        _ => u32_be(pitm, 4)?,
+    };
+
+    let ipma = find_box(iprp, b"ipma")?;
+
+    let version = *ipma.first()?;
+    let flags = u32_be(ipma, 0)? & 0xff_ffff;
+
+    let entry_count = u32_be(ipma, 4)?;
+    let mut at = 8;
+
+    for _ in 0..entry_count {
+        let item = if version < 1 {
+            at += 2;
+            u32::from(u16_be(ipma, at - 2)?)
+        } else {
+            at += 4;
+            u32_be(ipma, at - 4)?
+        };
+
+        let count = *ipma.get(at)? as usize;
+        at += 1;
+
+        let mut indices = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            // the highest bit is `essential`
+            let index = if flags & 1 == 1 {
+                at += 2;
+                usize::from(u16_be(ipma, at - 2)? & 0x7fff)
+            } else {
+                at += 1;
+                usize::from(ipma.get(at - 1)? & 0x7f)
+            };
+
+            indices.push(index);
+        }
+
+        if item == primary {
+            return Some(indices);
+        }
+    }
+
+    None
+}
+
+fn webp(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let chunk = buf.get(12..16)?;
+    let data = buf.get(20..)?;
+
+    match chunk {
+        b"VP8 " => {
+            // frame tag (3 bytes), start code
+            if data.get(3..6)? != [0x9d, 0x01, 0x2a] {
+                return None;
+            }
+
+            let width = u32::from(u16_le(data, 6)? & 0x3fff);
+            let height = u32::from(u16_le(data, 8)? & 0x3fff);
+
+            Some((width, height, false))
+        }
+        b"VP8L" => {
+            if *data.first()? != 0x2f {
+                return None;
+            }
+
+            let bits = u32_le(data, 1)?;
+
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+
+            Some((width, height, false))
+        }
+        b"VP8X" => {
+            let animated = data.first()? & 0x02 != 0;
+
+            let width = u24_le(data, 4)? + 1;
+            let height = u24_le(data, 7)? + 1;
+
+            Some((width, height, animated))
+        }
+        _ => None,
+    }
+}
+
+/// Reads bits from least significant bit as JPEG XL does
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: usize) -> Option<u32> {
+        let mut x = 0;
+
+        for i in 0..n {
+            let byte = self.buf.get(self.pos / 8)?;
+            let bit = (byte >> (self.pos % 8)) & 1;
+
+            x |= u32::from(bit) << i;
+            self.pos += 1;
+        }
+
+        Some(x)
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        Some(self.bits(1)? == 1)
+    }
+
+    /// `U32(d0, d1, d2, d3)` where each distribution is `(bits, offset)`
+    fn u32(&mut self, distributions: [(usize, u32); 4]) -> Option<u32> {
+        let (bits, offset) = distributions[self.bits(2)? as usize];
+
+        Some(self.bits(bits)? + offset)
+    }
+}
+
+const JXL_RATIOS: [(u32, u32); 7] = [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)];
+
+/// `(xsize, ysize)` of `SizeHeader` or `PreviewHeader`
+fn jxl_size(
+    reader: &mut BitReader,
+    size: impl Fn(&mut BitReader) -> Option<u32>,
+) -> Option<(u32, u32)> {
+    let height = size(reader)?;
+
+    let width = match reader.bits(3)? {
+        0 => size(reader)?,
+        ratio => {
+            let (num, den) = JXL_RATIOS[ratio as usize - 1];
+            (u64::from(height) * u64::from(num) / u64::from(den)) as u32
+        }
+    };
+
+    Some((width, height))
+}
+
+fn jxl_size_header(reader: &mut BitReader) -> Option<(u32, u32)> {
+    if reader.bool()? {
+        jxl_size(reader, |r| Some((r.bits(5)? + 1) * 8))
+    } else {
+        jxl_size(reader, |r| r.u32([(9, 1), (13, 1), (18, 1), (30, 1)]))
+    }
+}
+
+fn jxl_preview_header(reader: &mut BitReader) -> Option<(u32, u32)> {
+    if reader.bool()? {
+        jxl_size(reader, |r| {
+            Some(r.u32([(0, 16), (0, 32), (5, 1), (9, 33)])? * 8)
+        })
+    } else {
+        jxl_size(reader, |r| r.u32([(6, 1), (8, 65), (10, 321), (12, 1345)]))
+    }
+}
+
+fn jxl(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let codestream = match buf {
+        [0xff, 0x0a, ..] => buf,
+        _ => boxes(buf).find_map(|(kind, payload)| match &kind {
+            b"jxlc" => Some(payload),
+            // partial codestream has sequence number
+            b"jxlp" => payload.get(4..),
+            _ => None,
+        })?,
+    };
+
+    let mut reader = BitReader {
+        buf: codestream.get(2..)?,
+        pos: 0,
+    };
+
+    let (width, height) = jxl_size_header(&mut reader)?;
+
+    // ImageMetadata
+    let mut animated = false;
+    let mut orientation = 1;
+
+    let all_default = reader.bool()?;
+
+    if !all_default && reader.bool()? {
+        // extra_fields
+        orientation = reader.bits(3)? + 1;
+
+        // intrinsic size
+        if reader.bool()? {
+            jxl_size_header(&mut reader)?;
+        }
+
+        // preview
+        if reader.bool()? {
+            jxl_preview_header(&mut reader)?;
+        }
+
+        animated = reader.bool()?;
+    }
+
+    // transposed
+    let (width, height) = if orientation > 4 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    Some((width, height, animated))
+}
+
+fn jpeg(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let frame = jpeg_frame(buf)?;
+
+    Some((frame.width, frame.height, false))
+}
+
+/// Start of frame of JPEG
+#[cfg_attr(not(feature = "pdf"), allow(dead_code))]
+pub(crate) struct JpegFrame {
+    /// SOF0..SOF15, e.g. `0xc0` for baseline, `0xc2` for progressive
+    pub marker: u8,
+    pub precision: u8,
+    pub width: u32,
+    pub height: u32,
+    /// 1 for grayscale, 3 for YCbCr, 4 for CMYK
+    pub components: u8,
+}
+
+pub(crate) fn jpeg_frame(buf: &[u8]) -> Option<JpegFrame> {
+    let mut at = 2;
+
+    loop {
+        // skip fill bytes
+        while *buf.get(at)? == 0xff && *buf.get(at + 1)? == 0xff {
+            at += 1;
+        }
+
+        if *buf.get(at)? != 0xff {
+            return None;
+        }
+
+        let marker = *buf.get(at + 1)?;
+        let len = usize::from(u16_be(buf, at + 2)?);
+
+        // SOF0..SOF15 except DHT, JPG and DAC
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            return Some(JpegFrame {
+                marker,
+                precision: *buf.get(at + 4)?,
+                height: u32::from(u16_be(buf, at + 5)?),
+                width: u32::from(u16_be(buf, at + 7)?),
+                components: *buf.get(at + 9)?,
+            });
+        }
+
+        at += 2 + len;
+    }
+}
+
+fn png(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let width = u32_be(buf, 16)?;
+    let height = u32_be(buf, 20)?;
+
+    // `acTL` must precede `IDAT`
+    let mut at = 8;
+    let mut animated = false;
+
+    while let (Some(len), Some(kind)) = (u32_be(buf, at), buf.get(at + 4..at + 8)) {
+        match kind {
+            b"acTL" => {
+                animated = true;
+                break;
+            }
+            b"IDAT" => break,
+            _ => at += 12 + len as usize,
+        }
+    }
+
+    Some((width, height, animated))
+}
+
+fn gif(buf: &[u8]) -> Option<(u32, u32, bool)> {
+    let width = u32::from(u16_le(buf, 6)?);
+    let height = u32::from(u16_le(buf, 8)?);
+
+    // looping application extension
+    let animated = buf
+        .windows(11)
+        .any(|x| x == b"NETSCAPE2.0" || x == b"ANIMEXTS1.0");
+
+    Some((width, height, animated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bx(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut res = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        res.extend_from_slice(kind);
+        res.extend_from_slice(payload);
+        res
+    }
+
+    fn ispe(width: u32, height: u32) -> Vec<u8> {
+        bx(
+            b"ispe",
+            &[[0; 4], width.to_be_bytes(), height.to_be_bytes()].concat(),
+        )
+    }
+
+    #[test]
+    fn probe_avif() {
+        let ftyp = bx(b"ftyp", b"avif\0\0\0\0avifmif1miaf");
+
+        // primary item 1 has the second property
+        let pitm = bx(b"pitm", &[0, 0, 0, 0, 0, 1]);
+        let ipco = bx(b"ipco", &[ispe(10, 10), ispe(1280, 1810)].concat());
+        let ipma = bx(b"ipma", &[0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0x82]);
+        let iprp = bx(b"iprp", &[ipco, ipma].concat());
+        let meta = bx(b"meta", &[vec![0; 4], pitm, iprp].concat());
+
+        let buf = [ftyp, meta].concat();
+
+        assert_eq!(
+            probe(&buf),
+            Some(ImageInfo {
+                format: ImageFormat::Avif,
+                width: 1280,
+                height: 1810,
+                animated: false
+            })
+        );
+    }
+
+    #[test]
+    fn probe_webp() {
+        let riff = |chunk: &[u8; 4], data: &[u8]| {
+            [b"RIFF\0\0\0\0WEBP".as_slice(), chunk, &[0; 4], data].concat()
+        };
+
+        // lossy
+        let buf = riff(
+            b"VP8 ",
+            &[0, 0, 0, 0x9d, 0x01, 0x2a, 0x00, 0x05, 0x12, 0x07],
+        );
+        let info = probe(&buf).unwrap();
+        assert_eq!(
+            (info.width, info.height, info.animated),
+            (1280, 1810, false)
+        );
+
+        // lossless
+        let bits: u32 = (1280 - 1) | ((1810 - 1) << 14);
+        let buf = riff(b"VP8L", &[&[0x2f][..], &bits.to_le_bytes()].concat());
+        let info = probe(&buf).unwrap();
+        assert_eq!(
+            (info.width, info.height, info.animated),
+            (1280, 1810, false)
+        );
+
+        // extended
+        let buf = riff(
+            b"VP8X",
+            &[0x02, 0, 0, 0, 0xff, 0x04, 0x00, 0x11, 0x07, 0x00],
+        );
+        let info = probe(&buf).unwrap();
+        assert_eq!((info.width, info.height, info.animated), (1280, 1810, true));
+    }
+
+    #[test]
+    fn probe_jxl() {
+        struct BitWriter(Vec<bool>);
+
+        impl BitWriter {
+            fn bits(&mut self, n: usize, x: u32) {
+                for i in 0..n {
+                    self.0.push((x >> i) & 1 == 1);
+                }
+            }
+
+            fn bytes(&self) -> Vec<u8> {
+                self.0
+                    .chunks(8)
+                    .map(|bits| {
+                        bits.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, bit)| acc | (u8::from(*bit) << i))
+                    })
+                    .collect()
+            }
+        }
+
+        // 1280x1810: not small, height = 1 + u(13), ratio = 0, width = 1 + u(13)
+        let mut w = BitWriter(Vec::new());
+        w.bits(1, 0);
+        w.bits(2, 1);
+        w.bits(13, 1810 - 1);
+        w.bits(3, 0);
+        w.bits(2, 1);
+        w.bits(13, 1280 - 1);
+        // all_default
+        w.bits(1, 1);
+
+        let buf = [vec![0xff, 0x0a], w.bytes()].concat();
+        let info = probe(&buf).unwrap();
+        assert_eq!(
+            (info.width, info.height, info.animated),
+            (1280, 1810, false)
+        );
+
+        // 64x48 small with ratio 4:3, animated, in container
+        let mut w = BitWriter(Vec::new());
+        w.bits(1, 1);
+        w.bits(5, 48 / 8 - 1);
+        w.bits(3, 3);
+        // not all_default, extra_fields, orientation = 1, no intrinsic size, no preview, animation
+        w.bits(1, 0);
+        w.bits(1, 1);
+        w.bits(3, 0);
+        w.bits(1, 0);
+        w.bits(1, 0);
+        w.bits(1, 1);
+
+        let codestream = [vec![0xff, 0x0a], w.bytes()].concat();
+        let buf = [
+            b"\0\0\0\x0cJXL \r\n\x87\n".to_vec(),
+            bx(b"ftyp", b"jxl \0\0\0\0jxl "),
+            bx(b"jxlc", &codestream),
+        ]
+        .concat();
+        let info = probe(&buf).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height, info.animated),
+            (ImageFormat::Jxl, 64, 48, true)
+        );
+    }
+
+    #[test]
+    fn probe_originals() {
+        // APP0, SOF0
+        let jpeg = [
+            &[0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00][..],
+            &[0xff, 0xc0, 0x00, 0x11, 0x08, 0x07, 0x12, 0x05, 0x00, 0x03],
+        ]
+        .concat();
+        let info = probe(&jpeg).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Jpeg, 1280, 1810)
+        );
+
+        let png = [
+            &b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"[..],
+            &1280_u32.to_be_bytes(),
+            &1810_u32.to_be_bytes(),
+            &[8, 6, 0, 0, 0, 0, 0, 0, 0],
+            b"\0\0\0\x08acTL",
+        ]
+        .concat();
+        let info = probe(&png).unwrap();
+        assert_eq!((info.width, info.height, info.animated), (1280, 1810, true));
+
+        let gif = [
+            &b"GIF89a"[..],
+            &[0x00, 0x05, 0x12, 0x07],
+            b"!\xff\x0bNETSCAPE2.0",
+        ]
+        .concat();
+        let info = probe(&gif).unwrap();
+        assert_eq!((info.width, info.height, info.animated), (1280, 1810, true));
+    }
+}
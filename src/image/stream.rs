@@ -0,0 +1,455 @@
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use reqwest::{
+    header::{self, HeaderName, HeaderValue},
+    Method, StatusCode,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+use crate::{
+    gg::GG,
+    model::File,
+    network::{
+        self,
+        http::{request_with_headers, ContentRange},
+    },
+};
+
+use super::{extension_of, parse_url, Error, ImageExt, ImageKind, Verify};
+
+/// Position of partial image to resume downloading from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resume {
+    /// count of bytes already downloaded
+    pub offset: u64,
+    /// `ETag` of response which partial image came from
+    ///
+    /// If it's given, server sends whole image when image is changed.
+    pub etag: Option<String>,
+}
+
+/// Body of image which is not buffered
+pub struct ImageStream {
+    pub kind: ImageKind,
+    pub ext: ImageExt,
+    pub url: String,
+    /// `Content-Length` of response if server sent it
+    ///
+    /// It's length of remaining part when resumed.
+    pub content_length: Option<u64>,
+    /// Position where this stream starts in image
+    ///
+    /// It's `0` if server ignored range or image is changed,
+    /// in which case partial image must be discarded.
+    pub offset: u64,
+    /// Length of whole image if known
+    pub total_length: Option<u64>,
+    /// `ETag` of response, pass it to [`Resume::etag`] when resuming
+    pub etag: Option<String>,
+    pub(super) inner: BoxStream<'static, crate::Result<Bytes>>,
+}
+
+impl ImageStream {
+    /// See [`Image::extension`](super::Image::extension)
+    pub fn extension(&self) -> &str {
+        extension_of(&self.url, &self.ext)
+    }
+
+    /// Writes every chunk into `writer` and Returns count of bytes written
+    pub async fn write_to<W>(mut self, writer: &mut W) -> crate::Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut written = 0;
+
+        while let Some(chunk) = self.try_next().await? {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        writer.flush().await?;
+
+        Ok(written)
+    }
+}
+
+impl Stream for ImageStream {
+    type Item = crate::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl std::fmt::Debug for ImageStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageStream")
+            .field("kind", &self.kind)
+            .field("ext", &self.ext)
+            .field("url", &self.url)
+            .field("content_length", &self.content_length)
+            .field("offset", &self.offset)
+            .field("total_length", &self.total_length)
+            .field("etag", &self.etag)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Requests image and Returns its body as stream of chunks
+pub async fn stream(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+) -> crate::Result<ImageStream> {
+    stream_from(file, kind, ext, gg, &Resume::default()).await
+}
+
+/// Requests rest of image from `resume.offset` with `Range` header
+///
+/// Check [`ImageStream::offset`], server may send whole image instead.
+///
+/// ## Errors
+///
+/// - [`Error::ContentRange`] if server sent another range than requested
+pub async fn stream_from(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+    resume: &Resume,
+) -> crate::Result<ImageStream> {
+    let image_url = parse_url(file, kind, ext, gg)?;
+
+    let mut headers: Vec<(HeaderName, HeaderValue)> = Vec::new();
+
+    if resume.offset > 0 {
+        headers.push((
+            header::RANGE,
+            format!("bytes={}-", resume.offset).try_into().unwrap(),
+        ));
+
+        if let Some(etag) = resume.etag.as_deref().and_then(|x| x.try_into().ok()) {
+            headers.push((header::IF_RANGE, etag));
+        }
+    }
+
+    let resp = request_with_headers(Method::GET, headers.into_iter(), &image_url).await?;
+
+    let status = resp.status();
+
+    let content_range = ContentRange::from_response(&resp);
+
+    let etag = resp
+        .headers()
+        .get(header::ETAG)
+        .and_then(|x| x.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let content_length = resp.content_length();
+
+    tracing::debug!(?image_url, ?status, ?content_range, ?content_length);
+
+    let (offset, total_length) = match status {
+        StatusCode::PARTIAL_CONTENT if resume.offset > 0 => match content_range {
+            Some(ContentRange {
+                range: Some((start, _)),
+                total,
+            }) if start == resume.offset => (start, total),
+            _ => return Err(Error::ContentRange(resume.offset, content_range).into()),
+        },
+        // partial image is already whole image
+        StatusCode::RANGE_NOT_SATISFIABLE if resume.offset > 0 => match content_range {
+            Some(ContentRange {
+                range: None,
+                total: Some(total),
+            }) if total == resume.offset => {
+                return Ok(ImageStream {
+                    kind,
+                    ext,
+                    url: image_url,
+                    content_length: Some(0),
+                    offset: total,
+                    total_length: Some(total),
+                    etag,
+                    inner: futures::stream::empty().boxed(),
+                });
+            }
+            _ => return Err(Error::ContentRange(resume.offset, content_range).into()),
+        },
+        _ if status.is_success() => (0, content_length),
+        _ => return Err(network::http::Error::Status(status).into()),
+    };
+
+    let inner = futures::stream::try_unfold(resp, |mut resp| async move {
+        Ok(resp.chunk().await?.map(|chunk| (chunk, resp)))
+    })
+    .boxed();
+
+    Ok(ImageStream {
+        kind,
+        ext,
+        url: image_url,
+        content_length,
+        offset,
+        total_length,
+        etag,
+        inner,
+    })
+}
+
+/// Streams image into `writer` without buffering whole image
+///
+/// ## Return
+///
+/// Returns count of bytes written into `writer`
+pub async fn download_to<W>(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+    writer: &mut W,
+) -> crate::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    stream(file, kind, ext, gg).await?.write_to(writer).await
+}
+
+/// Streams image into file of `path`
+///
+/// Writes into `{path}.part` first and renames it to `path` when completed,
+/// so `path` never contains partial image.
+///
+/// If `{path}.part` is left by interrupted download, resumes from its end
+/// with `If-Range` of `ETag` saved in `{path}.part.etag`, or starts over without it.
+///
+/// ## Return
+///
+/// Returns count of bytes of image
+pub async fn download_to_path(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+    path: impl AsRef<Path>,
+) -> crate::Result<u64> {
+    download_to_path_verified(file, kind, ext, gg, path, Verify::none()).await
+}
+
+/// Same as [`download_to_path`], but checks image by `verify`
+///
+/// `{path}.part` is removed if check fails, so next download starts over.
+pub async fn download_to_path_verified(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+    path: impl AsRef<Path>,
+    verify: Verify,
+) -> crate::Result<u64> {
+    download_to_path_with(file, kind, ext, gg, path.as_ref(), verify, |_, _| {}).await
+}
+
+/// Same as [`download_to_path_verified`], calls `progress(downloaded, total)` for each chunk
+pub(crate) async fn download_to_path_with(
+    file: &File,
+    kind: ImageKind,
+    ext: ImageExt,
+    gg: &GG,
+    path: &Path,
+    verify: Verify,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> crate::Result<u64> {
+    let part = part_path(path);
+    let etag_path = etag_path(&part);
+
+    let offset = match fs::metadata(&part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    let etag = match offset {
+        0 => None,
+        _ => fs::read_to_string(&etag_path)
+            .await
+            .ok()
+            .filter(|etag| !etag.is_empty()),
+    };
+
+    // without `If-Range`, a changed image would be appended to stale partial image
+    let resume = match etag {
+        Some(etag) => Resume {
+            offset,
+            etag: Some(etag),
+        },
+        None => Resume::default(),
+    };
+
+    if resume.offset != offset {
+        tracing::debug!(?part, "no etag of partial image, start over");
+    }
+
+    let stream = stream_from(file, kind, ext, gg, &resume).await?;
+
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&part)
+        .await?;
+
+    let mut verifier = stream.verifier(file, verify);
+
+    if stream.offset != offset {
+        if resume.offset > 0 {
+            tracing::debug!(?part, "server sent whole image, discard partial image");
+        }
+
+        f.set_len(0).await?;
+    }
+
+    if stream.offset == 0 {
+        match &stream.etag {
+            Some(etag) => fs::write(&etag_path, etag).await?,
+            None => remove_if_exists(&etag_path).await?,
+        }
+    } else {
+        // feed partial image, reading position is independent from appending
+        let mut buf = vec![0; 64 * 1024];
+
+        loop {
+            let n = f.read(&mut buf).await?;
+
+            if n == 0 {
+                break;
+            }
+
+            verifier.update(&buf[..n]);
+        }
+    }
+
+    let offset = stream.offset;
+    let total = stream.total_length;
+
+    let mut stream = stream.verify_with(verifier);
+
+    let write = async {
+        let mut written = 0;
+
+        while let Some(chunk) = stream.try_next().await? {
+            f.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+
+            progress(offset + written, total);
+        }
+
+        f.flush().await?;
+
+        Ok::<_, crate::Error>(written)
+    };
+
+    let written = match write.await {
+        Ok(written) => written,
+        Err(err @ crate::Error::Image(Error::Integrity(_))) => {
+            drop(f);
+            fs::remove_file(&part).await.ok();
+            fs::remove_file(&etag_path).await.ok();
+            return Err(err);
+        }
+        // keep `{path}.part` on failure to resume later
+        Err(err) => return Err(err),
+    };
+
+    f.sync_all().await?;
+    drop(f);
+
+    fs::rename(&part, path).await?;
+    remove_if_exists(&etag_path).await?;
+
+    Ok(offset + written)
+}
+
+async fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// `{path}.part`
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// `{part}.etag`, `ETag` of response which `{part}` came from
+fn etag_path(part: &Path) -> PathBuf {
+    let mut etag = part.as_os_str().to_owned();
+    etag.push(".etag");
+    PathBuf::from(etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gallery, tests::tracing};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn download_image_to_path() {
+        tracing();
+
+        let id = 3014301;
+
+        let gallery_dir = format!("./sample/images/{id}");
+        std::fs::create_dir_all(&gallery_dir).unwrap();
+
+        let gallery = gallery::parse(id).await.unwrap().unwrap();
+
+        let (_, file) = &gallery.files[0];
+
+        let gg = GG::from_hitomi().await.unwrap();
+
+        let path = PathBuf::from(format!("{gallery_dir}/streamed.avif"));
+
+        let written = download_to_path(file, ImageKind::Original, ImageExt::Avif, &gg, &path)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), written);
+        assert!(!part_path(&path).exists());
+
+        let whole = std::fs::read(&path).unwrap();
+
+        let etag = stream(file, ImageKind::Original, ImageExt::Avif, &gg)
+            .await
+            .unwrap()
+            .etag
+            .unwrap();
+
+        // interrupted at half, resumed with etag, changed image and without etag
+        for etag in [etag.as_str(), "\"changed\"", ""] {
+            std::fs::write(part_path(&path), &whole[..whole.len() / 2]).unwrap();
+            std::fs::write(etag_path(&part_path(&path)), etag).unwrap();
+
+            let written = download_to_path(file, ImageKind::Original, ImageExt::Avif, &gg, &path)
+                .await
+                .unwrap();
+
+            assert_eq!(written, whole.len() as u64, "{etag}");
+            assert_eq!(std::fs::read(&path).unwrap(), whole, "{etag}");
+            assert!(!etag_path(&part_path(&path)).exists());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Cursor;
+
+use ::image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat as Format};
+use bytes::Bytes;
+
+use super::{sniff, Error, Image, ImageExt, ImageFormat};
+
+/// Format to transcode into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetFormat {
+    Png,
+    Jpeg,
+}
+
+impl TargetFormat {
+    pub fn ext(&self) -> ImageExt {
+        match self {
+            TargetFormat::Png => ImageExt::Png,
+            TargetFormat::Jpeg => ImageExt::Jpeg,
+        }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        match self {
+            TargetFormat::Png => ImageFormat::Png,
+            TargetFormat::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+}
+
+/// Decodes `image` and encodes it as `target`
+///
+/// `quality` is from 1 to 100 and used only for JPEG.
+/// Only the first frame of animated image is kept.
+///
+/// ## Errors
+///
+/// - [`Error::UnsupportedTranscode`] if there's no pure Rust decoder for `image`, e.g. AVIF and JXL
+pub fn transcode(image: &Image, target: TargetFormat, quality: u8) -> Result<Image, Error> {
+    let format = match sniff(&image.buf) {
+        Some(ImageFormat::Webp) => Format::WebP,
+        Some(ImageFormat::Jpeg) => Format::Jpeg,
+        Some(ImageFormat::Png) => Format::Png,
+        Some(ImageFormat::Gif) => Format::Gif,
+        Some(ImageFormat::Avif | ImageFormat::Jxl) | None => {
+            return Err(Error::UnsupportedTranscode(image.ext))
+        }
+    };
+
+    let decoded = ::image::load_from_memory_with_format(&image.buf, format)?;
+
+    let mut buf = Vec::new();
+
+    match target {
+        TargetFormat::Png => {
+            decoded.write_to(&mut Cursor::new(&mut buf), Format::Png)?;
+        }
+        TargetFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let rgb = DynamicImage::ImageRgb8(decoded.to_rgb8());
+
+            let encoder = JpegEncoder::new_with_quality(&mut buf, quality.clamp(1, 100));
+
+            rgb.write_with_encoder(encoder)?;
+        }
+    }
+
+    tracing::debug!(from = ?image.ext, to = ?target, before = image.buf.len(), after = buf.len());
+
+    Ok(Image {
+        kind: image.kind,
+        ext: target.ext(),
+        url: image.url.clone(),
+        buf: Bytes::from(buf),
+    })
+}
+
+impl Image {
+    /// See [`transcode`]
+    pub fn transcode(&self, target: TargetFormat, quality: u8) -> Result<Image, Error> {
+        transcode(self, target, quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::image::{codecs::webp::WebPEncoder, ImageEncoder, RgbaImage};
+
+    use crate::image::{probe, ImageKind};
+
+    use super::*;
+
+    #[test]
+    fn transcode_webp() {
+        let mut webp = Vec::new();
+
+        WebPEncoder::new_lossless(&mut webp)
+            .write_image(
+                RgbaImage::from_pixel(4, 3, [255, 0, 0, 255].into()).as_raw(),
+                4,
+                3,
+                ::image::ExtendedColorType::Rgba8,
+            )
+            .unwrap();
+
+        let image = Image {
+            kind: ImageKind::Original,
+            ext: ImageExt::Webp,
+            url: "https://w1.example.com/1/2/hash.webp".to_owned(),
+            buf: Bytes::from(webp),
+        };
+
+        for (target, format) in [
+            (TargetFormat::Png, ImageFormat::Png),
+            (TargetFormat::Jpeg, ImageFormat::Jpeg),
+        ] {
+            let transcoded = image.transcode(target, 90).unwrap();
+
+            assert_eq!(transcoded.ext, target.ext());
+            assert_eq!(transcoded.extension(), target.ext().as_str());
+
+            let info = probe(&transcoded.buf).unwrap();
+
+            assert_eq!((info.format, info.width, info.height), (format, 4, 3));
+        }
+
+        let avif = Image {
+            ext: ImageExt::Avif,
+            buf: Bytes::from_static(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"),
+            ..image
+        };
+
+        assert!(matches!(
+            avif.transcode(TargetFormat::Png, 90),
+            Err(Error::UnsupportedTranscode(ImageExt::Avif))
+        ));
+    }
+}
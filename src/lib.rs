@@ -2,13 +2,17 @@
 //!
 //! A hitomi.la API wrapper for Rust programming language.
 
+mod date;
+pub mod download;
 pub mod error;
+pub mod export;
 pub mod gallery;
 pub mod gg;
 pub mod image;
 pub mod model;
 pub mod network;
 pub mod nozomi;
+pub mod video;
 
 pub use error::Error;
 
@@ -16,6 +20,60 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::model::{File, Gallery};
+
+    /// Creates a directory under temp dir which no other test or run shares
+    pub fn temp_dir(name: &str) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "hitomi_la_{name}_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    /// File named `{hash}.jpg`, served as WebP and AVIF
+    pub fn file(hash: &str) -> File {
+        File {
+            has_webp: true,
+            has_avif: true,
+            has_jxl: false,
+            width: 800,
+            height: 1200,
+            hash: hash.to_owned(),
+            name: format!("{hash}.jpg"),
+        }
+    }
+
+    /// Gallery of [`file`]s of `hashes`, fields of which tests override with `..gallery(..)`
+    pub fn gallery(hashes: &[&str]) -> Gallery {
+        Gallery {
+            id: 123,
+            title: "title".to_owned(),
+            kind: "manga".to_owned(),
+            files: hashes
+                .iter()
+                .enumerate()
+                .map(|(i, hash)| (i + 1, file(hash)))
+                .collect(),
+            video: None,
+            language: None,
+            tags: vec![],
+            date_added: "2024-01-02T03:04:05Z".parse().unwrap(),
+            date_published: None,
+        }
+    }
+
     pub fn tracing() {
         if std::env::args().any(|arg| arg == "--nocapture") {
             let subscriber = tracing_subscriber::fmt()
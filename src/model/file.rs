@@ -1,11 +1,55 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::{
+    gg::GG,
+    image::{self, ImageExt, ImageKind, ImageUrl},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct File {
     pub has_webp: bool,
     pub has_avif: bool,
+    #[serde(default)]
+    pub has_jxl: bool,
     pub width: usize,
     pub height: usize,
     pub hash: String,
     pub name: String,
 }
+
+impl File {
+    /// Returns extension of uploaded file from `name`, e.g. `jpg`
+    pub fn original_ext(&self) -> Option<&str> {
+        self.name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .filter(|ext| !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_alphanumeric()))
+    }
+
+    /// Returns file extension of image served as `ext`, e.g. `avif`, `jpg`
+    pub fn extension(&self, ext: ImageExt) -> &str {
+        match ext {
+            ImageExt::Original => self.original_ext().unwrap_or(ext.as_str()),
+            _ => ext.as_str(),
+        }
+    }
+
+    /// Returns whether hitomi serves image as `ext`
+    ///
+    /// For [`ImageExt::Original`] it only checks that `name` has an extension, hitomi may still
+    /// respond 404.
+    pub fn has(&self, ext: ImageExt) -> bool {
+        match ext {
+            ImageExt::Avif => self.has_avif,
+            ImageExt::Webp => self.has_webp,
+            ImageExt::Jxl => self.has_jxl,
+            ImageExt::Original => self.original_ext().is_some(),
+            ImageExt::Png | ImageExt::Jpeg => false,
+        }
+    }
+
+    /// See [`image::url`]
+    pub fn url(&self, kind: ImageKind, ext: ImageExt, gg: &GG) -> Result<ImageUrl, image::Error> {
+        image::url(self, kind, ext, gg)
+    }
+}
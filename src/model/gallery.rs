@@ -2,6 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use super::*;
+use crate::network::http::BASE_URL;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Gallery {
@@ -12,7 +13,78 @@ pub struct Gallery {
     ///
     /// page starts from 1
     pub files: Vec<(usize, File)>,
+    /// only `anime` gallery has video
+    pub video: Option<Video>,
     pub language: Option<String>,
     pub tags: Vec<Tag>,
-    pub date: DateTime<Utc>,
+    #[serde(alias = "date")]
+    pub date_added: DateTime<Utc>,
+    /// `datepublished`, date of original publication if known
+    #[serde(default)]
+    pub date_published: Option<DateTime<Utc>>,
+}
+
+impl Gallery {
+    /// Returns names of tags of `kind`, e.g. artists
+    pub fn tag_names(&self, kind: TagKind) -> impl Iterator<Item = &str> {
+        self.tags
+            .iter()
+            .filter(move |tag| tag.kind == kind)
+            .map(|tag| tag.name.as_str())
+    }
+
+    /// Returns url of gallery page, e.g. `https://hitomi.la/galleries/123.html`
+    pub fn url(&self) -> String {
+        format!("{}/galleries/{}.html", BASE_URL, self.id)
+    }
+
+    /// Returns ISO 639-1 code of `language`, e.g. `ko` for `korean`
+    pub fn language_code(&self) -> Option<&'static str> {
+        let code = match self.language.as_deref()? {
+            "albanian" => "sq",
+            "arabic" => "ar",
+            "bulgarian" => "bg",
+            "catalan" => "ca",
+            "chinese" => "zh",
+            "czech" => "cs",
+            "danish" => "da",
+            "dutch" => "nl",
+            "english" => "en",
+            "esperanto" => "eo",
+            "estonian" => "et",
+            "finnish" => "fi",
+            "french" => "fr",
+            "german" => "de",
+            "greek" => "el",
+            "hebrew" => "he",
+            "hindi" => "hi",
+            "hungarian" => "hu",
+            "icelandic" => "is",
+            "indonesian" => "id",
+            "italian" => "it",
+            "japanese" => "ja",
+            "javanese" => "jv",
+            "korean" => "ko",
+            "latin" => "la",
+            "mongolian" => "mn",
+            "norwegian" => "no",
+            "persian" => "fa",
+            "polish" => "pl",
+            "portuguese" => "pt",
+            "romanian" => "ro",
+            "russian" => "ru",
+            "serbian" => "sr",
+            "slovak" => "sk",
+            "spanish" => "es",
+            "swedish" => "sv",
+            "tagalog" => "tl",
+            "thai" => "th",
+            "turkish" => "tr",
+            "ukrainian" => "uk",
+            "vietnamese" => "vi",
+            _ => return None,
+        };
+
+        Some(code)
+    }
 }
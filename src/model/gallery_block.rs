@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Summary of a gallery as rendered in listings (`galleryblock/{id}.html`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GalleryBlock {
+    pub id: u32,
+    pub title: String,
+    pub kind: String,
+    pub language: Option<String>,
+    pub artists: Vec<String>,
+    pub series: Vec<String>,
+    /// groups, characters and tags
+    pub tags: Vec<Tag>,
+    pub date: DateTime<Utc>,
+    /// absolute thumbnail urls in the order they appear
+    pub thumbnails: Vec<String>,
+}
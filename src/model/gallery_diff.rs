@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq + Clone> Change<T> {
+    fn of(old: &T, new: &T) -> Option<Self> {
+        (old != new).then(|| Self {
+            old: old.clone(),
+            new: new.clone(),
+        })
+    }
+}
+
+/// File which exists in both galleries but on another page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMove {
+    pub hash: String,
+    /// page in old gallery
+    pub from: usize,
+    /// page in new gallery
+    pub to: usize,
+}
+
+/// Changes between two fetches of the same gallery
+///
+/// Files are compared by `hash`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GalleryDiff {
+    pub title: Option<Change<String>>,
+    pub kind: Option<Change<String>>,
+    pub language: Option<Change<Option<String>>>,
+    pub added_tags: Vec<Tag>,
+    pub removed_tags: Vec<Tag>,
+    /// (page in new gallery, File)
+    pub added_files: Vec<(usize, File)>,
+    /// (page in old gallery, File)
+    pub removed_files: Vec<(usize, File)>,
+    /// files out of their previous order
+    ///
+    /// shifted files by added or removed files are not included
+    pub reordered_files: Vec<FileMove>,
+}
+
+impl GalleryDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Gallery {
+    /// Returns changes from `self` to `other`
+    pub fn diff(&self, other: &Gallery) -> GalleryDiff {
+        let old_tags = self.tags.iter().collect::<HashSet<_>>();
+        let new_tags = other.tags.iter().collect::<HashSet<_>>();
+
+        let added_tags = other
+            .tags
+            .iter()
+            .filter(|tag| !old_tags.contains(tag))
+            .cloned()
+            .collect();
+
+        let removed_tags = self
+            .tags
+            .iter()
+            .filter(|tag| !new_tags.contains(tag))
+            .cloned()
+            .collect();
+
+        //
+
+        // same image can appear more than once, so (hash, nth occurrence) identifies a file
+        let old_keys = file_keys(&self.files);
+        let new_keys = file_keys(&other.files);
+
+        let new_index = new_keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, i))
+            .collect::<HashMap<_, _>>();
+        let old_index = old_keys.iter().copied().collect::<HashSet<_>>();
+
+        let mut removed_files = Vec::new();
+        // (index in old, index in new)
+        let mut common = Vec::new();
+
+        for (i, key) in old_keys.iter().enumerate() {
+            match new_index.get(key) {
+                Some(j) => common.push((i, *j)),
+                None => removed_files.push(self.files[i].clone()),
+            }
+        }
+
+        let added_files = new_keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !old_index.contains(*key))
+            .map(|(j, _)| other.files[j].clone())
+            .collect();
+
+        let in_order = longest_increasing(&common.iter().map(|(_, j)| *j).collect::<Vec<_>>());
+
+        let reordered_files = common
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| !in_order.contains(k))
+            .map(|(_, (i, j))| FileMove {
+                hash: self.files[*i].1.hash.clone(),
+                from: self.files[*i].0,
+                to: other.files[*j].0,
+            })
+            .collect();
+
+        GalleryDiff {
+            title: Change::of(&self.title, &other.title),
+            kind: Change::of(&self.kind, &other.kind),
+            language: Change::of(&self.language, &other.language),
+            added_tags,
+            removed_tags,
+            added_files,
+            removed_files,
+            reordered_files,
+        }
+    }
+}
+
+fn file_keys(files: &[(usize, File)]) -> Vec<(&str, usize)> {
+    let mut occurrences = HashMap::<&str, usize>::new();
+
+    files
+        .iter()
+        .map(|(_, file)| {
+            let n = occurrences.entry(&file.hash).or_default();
+            *n += 1;
+            (file.hash.as_str(), *n)
+        })
+        .collect()
+}
+
+/// Returns indices of longest strictly increasing subsequence of `xs`
+fn longest_increasing(xs: &[usize]) -> HashSet<usize> {
+    // tails[len] = index of smallest tail of increasing subsequence of length len + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; xs.len()];
+
+    for (i, x) in xs.iter().enumerate() {
+        let len = tails.partition_point(|&t| xs[t] < *x);
+
+        prev[i] = len.checked_sub(1).map(|l| tails[l]);
+
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+
+    let mut res = HashSet::with_capacity(tails.len());
+    let mut cur = tails.last().copied();
+
+    while let Some(i) = cur {
+        res.insert(i);
+        cur = prev[i];
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gallery(title: &str, tags: &[&str], hashes: &[&str]) -> Gallery {
+        Gallery {
+            id: 1,
+            title: title.to_owned(),
+            language: Some("korean".to_owned()),
+            tags: tags
+                .iter()
+                .map(|name| Tag {
+                    kind: TagKind::Misc,
+                    name: (*name).to_owned(),
+                })
+                .collect(),
+            ..crate::tests::gallery(hashes)
+        }
+    }
+
+    #[test]
+    fn diff_gallery() {
+        let old = gallery("a", &["x", "y"], &["1", "2", "3", "4", "5"]);
+
+        assert!(old.diff(&old).is_empty());
+
+        // "1" is moved behind "2", "3" and "4", which stay in order
+        let new = gallery("b", &["y", "z"], &["0", "2", "3", "4", "1", "6"]);
+
+        let diff = old.diff(&new);
+
+        assert_eq!(
+            diff.title,
+            Some(Change {
+                old: "a".to_owned(),
+                new: "b".to_owned()
+            })
+        );
+        assert_eq!(diff.kind, None);
+        assert_eq!(diff.language, None);
+        assert_eq!(diff.added_tags[0].name, "z");
+        assert_eq!(diff.removed_tags[0].name, "x");
+        assert_eq!(
+            diff.added_files
+                .iter()
+                .map(|(p, f)| (*p, &*f.hash))
+                .collect::<Vec<_>>(),
+            [(1, "0"), (6, "6")]
+        );
+        assert_eq!(
+            diff.removed_files
+                .iter()
+                .map(|(p, f)| (*p, &*f.hash))
+                .collect::<Vec<_>>(),
+            [(5, "5")]
+        );
+        assert_eq!(
+            diff.reordered_files,
+            [FileMove {
+                hash: "1".to_owned(),
+                from: 1,
+                to: 5
+            }]
+        );
+
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<GalleryDiff>(&json).unwrap(), diff);
+    }
+}
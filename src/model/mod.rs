@@ -1,7 +1,13 @@
 mod file;
 mod gallery;
+mod gallery_block;
+mod gallery_diff;
 mod tag;
+mod video;
 
 pub use file::*;
 pub use gallery::*;
+pub use gallery_block::*;
+pub use gallery_diff::*;
 pub use tag::*;
+pub use video::*;
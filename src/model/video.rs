@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Video of `anime` gallery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Video {
+    /// `video`
+    pub name: String,
+    /// `videofilename`, used for streaming path
+    pub file_name: String,
+}
@@ -1,12 +1,21 @@
-use std::time::Duration;
+use std::{sync::LazyLock, time::Duration};
 
 use reqwest::{
-    Method, Response, StatusCode,
     header::{self, HeaderMap, HeaderName, HeaderValue},
+    Method, Response, StatusCode,
 };
 
 pub const BASE_DOMAIN: &str = "gold-usergeneratedcontent.net";
 
+/// Site of hitomi which gallery pages are under
+pub const BASE_URL: &str = "https://hitomi.la";
+
+/// Referer required by hitomi servers
+pub const REFERER: &str = "https://hitomi.la";
+
+static CLIENT: LazyLock<reqwest::Client> =
+    LazyLock::new(|| reqwest::Client::builder().zstd(true).build().unwrap());
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Reqwest: {0}")]
@@ -16,6 +25,49 @@ pub enum Error {
     Status(StatusCode),
 }
 
+/// `Content-Range: bytes {start}-{end}/{total}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// `None` if `bytes */{total}`
+    pub range: Option<(u64, u64)>,
+    /// `None` if `bytes {start}-{end}/*`
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (range, total) = s.trim().strip_prefix("bytes ")?.split_once('/')?;
+
+        let range = match range {
+            "*" => None,
+            _ => {
+                let (start, end) = range.split_once('-')?;
+                Some((start.parse().ok()?, end.parse().ok()?))
+            }
+        };
+
+        let total = match total {
+            "*" => None,
+            _ => Some(total.parse().ok()?),
+        };
+
+        Some(Self { range, total })
+    }
+
+    pub fn from_response(resp: &Response) -> Option<Self> {
+        let x = resp.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+
+        Self::parse(x)
+    }
+}
+
+/// Returns client shared by every request of this crate
+///
+/// Connection pool is shared, so concurrent requests reuse connections to hitomi.
+pub fn client() -> &'static reqwest::Client {
+    &CLIENT
+}
+
 pub async fn request(method: Method, url: &str) -> Result<Response, Error> {
     request_with_headers(method, std::iter::empty(), url).await
 }
@@ -25,11 +77,9 @@ pub async fn request_with_headers(
     headers: impl Iterator<Item = (HeaderName, HeaderValue)>,
     url: &str,
 ) -> Result<Response, Error> {
-    let client = reqwest::Client::builder().zstd(true).build().unwrap();
-
-    let mut request = client
+    let mut request = client()
         .request(method, url)
-        .header(header::REFERER, "https://hitomi.la")
+        .header(header::REFERER, REFERER)
         .headers(HeaderMap::from_iter(headers));
 
     let is_ltn = url.starts_with("https://ltn.");
@@ -60,3 +110,34 @@ pub async fn request_with_headers(
 
     Ok(resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_content_range() {
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/200"),
+            Some(ContentRange {
+                range: Some((100, 199)),
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes */200"),
+            Some(ContentRange {
+                range: None,
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes 0-9/*"),
+            Some(ContentRange {
+                range: Some((0, 9)),
+                total: None
+            })
+        );
+        assert_eq!(ContentRange::parse("items 0-9/10"), None);
+    }
+}
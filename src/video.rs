@@ -0,0 +1,146 @@
+use reqwest::{
+    header::{self, HeaderName, HeaderValue},
+    Method, StatusCode, Url,
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    model::Video,
+    network::{
+        self,
+        http::{request_with_headers, ContentRange, BASE_DOMAIN},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unexpected content range: requested = {0}; received = {1:?}")]
+    ContentRange(u64, Option<String>),
+}
+
+/// `file_name` is percent-encoded as a path segment
+pub fn url(video: &Video) -> String {
+    let mut url = Url::parse(&format!("https://streaming.{}/videos/", BASE_DOMAIN)).unwrap();
+
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .push(&video.file_name);
+
+    url.into()
+}
+
+/// Streams video into `writer` starting from `offset` byte
+///
+/// Requests `Range: bytes={offset}-` to resume interrupted download,
+/// and skips already written bytes if server ignores range.
+///
+/// ## Return
+///
+/// Returns count of bytes written into `writer`
+pub async fn download<W>(video: &Video, offset: u64, writer: &mut W) -> crate::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let video_url = url(video);
+
+    tracing::debug!(?video_url, offset);
+
+    let range = (offset > 0).then(|| {
+        let range: (HeaderName, HeaderValue) = (
+            header::RANGE,
+            format!("bytes={}-", offset).try_into().unwrap(),
+        );
+        range
+    });
+
+    let mut resp = request_with_headers(Method::GET, range.into_iter(), &video_url).await?;
+
+    let status = resp.status();
+
+    // skip bytes already written
+    let mut skip = match status {
+        StatusCode::PARTIAL_CONTENT => {
+            let start = ContentRange::from_response(&resp)
+                .and_then(|x| x.range)
+                .map(|(start, _)| start);
+
+            if start != Some(offset) {
+                let content_range = resp
+                    .headers()
+                    .get(header::CONTENT_RANGE)
+                    .and_then(|x| x.to_str().ok())
+                    .map(ToOwned::to_owned);
+
+                return Err(Error::ContentRange(offset, content_range).into());
+            }
+
+            0
+        }
+        // already downloaded entirely
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => return Ok(0),
+        _ if status.is_success() => offset,
+        _ => return Err(network::http::Error::Status(status).into()),
+    };
+
+    let mut written = 0;
+
+    while let Some(chunk) = resp.chunk().await? {
+        let chunk = if skip > 0 {
+            let n = skip.min(chunk.len() as u64);
+            skip -= n;
+            chunk.slice(n as usize..)
+        } else {
+            chunk
+        };
+
+        writer.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+
+    writer.flush().await?;
+
+    tracing::debug!(written);
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gallery, tests::tracing};
+
+    use super::*;
+
+    #[test]
+    fn encode_url() {
+        let video = Video {
+            name: "video".to_owned(),
+            file_name: "a b#1?.mp4".to_owned(),
+        };
+
+        assert_eq!(
+            url(&video),
+            format!("https://streaming.{BASE_DOMAIN}/videos/a%20b%231%3F.mp4")
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "use many network resource"]
+    async fn download_video() {
+        tracing();
+
+        let gallery = gallery::parse(1284305).await.unwrap().unwrap();
+
+        let video = gallery.video.unwrap();
+
+        std::fs::create_dir_all("./sample/videos").unwrap();
+
+        let mut f = tokio::fs::File::create(format!("./sample/videos/{}", video.file_name))
+            .await
+            .unwrap();
+
+        let written = download(&video, 0, &mut f).await.unwrap();
+
+        assert!(written > 0);
+    }
+}